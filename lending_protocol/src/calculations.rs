@@ -33,10 +33,89 @@ pub fn calculate_borrow_rate(
     }
 }
 
-pub fn calculate_borrow_apr(borrow_rate: Decimal, updated_at: u64) -> Decimal {
-    let borrow_apr =
-        borrow_rate * (Runtime::current_epoch().number() - updated_at) / (365 * 24 * 60 / 5);
-    borrow_apr
+pub const SECONDS_PER_YEAR: i64 = 365 * 24 * 60 * 60;
+
+/// Approximate length of an epoch, used to estimate the time of a future epoch
+pub const SECONDS_PER_EPOCH: i64 = 5 * 60;
+
+pub fn get_current_time() -> i64 {
+    Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch
+}
+
+/// Growth of a balance over the elapsed period when the yearly rate is compounded every
+/// second: `(1 + rate / SECONDS_PER_YEAR) ^ elapsed_seconds - 1`. Accruing once over a
+/// period or many times over its sub-periods gives the same result.
+pub fn calculate_compounded_rate(borrow_rate: Decimal, elapsed_seconds: i64) -> Decimal {
+    if elapsed_seconds <= 0 || borrow_rate <= Decimal::ZERO {
+        return Decimal::ZERO;
+    }
+    let rate_per_second = PreciseDecimal::from(borrow_rate) / SECONDS_PER_YEAR;
+    let growth = (PreciseDecimal::ONE + rate_per_second)
+        .checked_powi(elapsed_seconds)
+        .expect("Compounded rate overflow")
+        - PreciseDecimal::ONE;
+    Decimal::try_from(growth).expect("Compounded rate overflow")
+}
+
+/// Factor `(1 + rate / SECONDS_PER_YEAR) ^ elapsed_seconds` by which a value moving at the
+/// given yearly rate changes over the elapsed period. The rate may be negative.
+pub fn calculate_growth_factor(rate: Decimal, elapsed_seconds: i64) -> Decimal {
+    if elapsed_seconds <= 0 {
+        return Decimal::ONE;
+    }
+    let rate_per_second = PreciseDecimal::from(rate) / SECONDS_PER_YEAR;
+    let factor = (PreciseDecimal::ONE + rate_per_second)
+        .checked_powi(elapsed_seconds)
+        .expect("Growth factor overflow");
+    Decimal::try_from(factor).expect("Growth factor overflow")
+}
+
+/// Distance of the utilisation from the target, normalised to [-1, 1]
+pub fn calculate_utilisation_error(utilisation: Decimal, target_utilisation: Decimal) -> Decimal {
+    if utilisation > target_utilisation {
+        (utilisation - target_utilisation) / (Decimal::ONE - target_utilisation)
+    } else {
+        (utilisation - target_utilisation) / target_utilisation
+    }
+}
+
+/// Rate at target of an adaptive curve after the pool spent the elapsed period at the given
+/// utilisation. It grows above the target and decays below it, at up to `adjustment_speed`
+/// per year.
+pub fn calculate_adapted_rate_at_target(
+    rate_at_target: Decimal,
+    utilisation: Decimal,
+    target_utilisation: Decimal,
+    adjustment_speed: Decimal,
+    elapsed_seconds: i64,
+) -> Decimal {
+    let error = calculate_utilisation_error(utilisation, target_utilisation);
+    rate_at_target * calculate_growth_factor(adjustment_speed * error, elapsed_seconds)
+}
+
+/// Start of the period utilisation has stayed at or above the rebalance utilisation, 0 while
+/// it is below. `utilisation` is the utilisation since `last_accrued_at`.
+pub fn calculate_stable_rebalance_since(
+    stable_rebalance_since: i64,
+    last_accrued_at: i64,
+    utilisation: Decimal,
+    stable_rebalance_utilisation: Decimal,
+) -> i64 {
+    if utilisation < stable_rebalance_utilisation {
+        0
+    } else if stable_rebalance_since == 0 {
+        last_accrued_at
+    } else {
+        stable_rebalance_since
+    }
+}
+
+pub fn is_stable_rebalance_allowed(
+    stable_rebalance_since: i64,
+    now: i64,
+    stable_rebalance_min_duration: i64,
+) -> bool {
+    stable_rebalance_since > 0 && now - stable_rebalance_since >= stable_rebalance_min_duration
 }
 
 pub fn calculate_interests(
@@ -44,12 +123,24 @@ pub fn calculate_interests(
     borrow_apr: Decimal,
     reserve_factor: Decimal,
 ) -> (Decimal, Decimal, Decimal) {
-    let borrow_interest = total_borrow * borrow_apr;
+    let borrow_interest = calculate_debt_interest(total_borrow, borrow_apr);
     let reserve_interest = borrow_interest * reserve_factor;
     let deposit_interest = borrow_interest - reserve_interest;
     (borrow_interest, reserve_interest, deposit_interest)
 }
 
+/// Interest on a debt, rounded up
+pub fn calculate_debt_interest(debt: Decimal, rate: Decimal) -> Decimal {
+    round_to_decimal(
+        PreciseDecimal::from(debt) * PreciseDecimal::from(rate),
+        RoundingMode::ToPositiveInfinity,
+    )
+}
+
+pub fn calculate_index(index: Decimal, rate: Decimal) -> Decimal {
+    index * (Decimal::ONE + rate)
+}
+
 pub fn calculate_token_price(total: Decimal, total_s: Decimal) -> Decimal {
     let mut token_price = Decimal::one();
     if total_s != Decimal::zero() {
@@ -58,13 +149,328 @@ pub fn calculate_token_price(total: Decimal, total_s: Decimal) -> Decimal {
     token_price
 }
 
-pub fn calculate_s_interest(amount: Decimal, total: Decimal, total_s: Decimal) -> Decimal {
-    let s_interest;
-    if total == Decimal::zero() {
-        s_interest = amount;
+/// Converts an amount of the pool asset to sd/sb tokens at the pool exchange rate
+pub fn calculate_s_interest(
+    amount: Decimal,
+    total: Decimal,
+    total_s: Decimal,
+    rounding_mode: RoundingMode,
+) -> Decimal {
+    if total == Decimal::zero() || total_s == Decimal::zero() {
+        return amount;
+    }
+    let s_interest =
+        PreciseDecimal::from(amount) * PreciseDecimal::from(total_s) / PreciseDecimal::from(total);
+    round_to_decimal(s_interest, rounding_mode)
+}
+
+/// Converts sd/sb tokens to an amount of the pool asset at the pool exchange rate
+pub fn calculate_s_value(
+    s_amount: Decimal,
+    total: Decimal,
+    total_s: Decimal,
+    rounding_mode: RoundingMode,
+) -> Decimal {
+    if total_s == Decimal::zero() {
+        return s_amount;
+    }
+    let value = PreciseDecimal::from(s_amount) * PreciseDecimal::from(total)
+        / PreciseDecimal::from(total_s);
+    round_to_decimal(value, rounding_mode)
+}
+
+// Rounding policy: every conversion between the pool asset and sd/sb tokens rounds in
+// favour of the protocol, so repeating operations can never extract value from the pool.
+
+/// sd tokens minted for a deposit, rounded down
+pub fn calculate_sd_to_mint(
+    amount: Decimal,
+    deposit_balance: Decimal,
+    sd_balance: Decimal,
+) -> Decimal {
+    calculate_s_interest(
+        amount,
+        deposit_balance,
+        sd_balance,
+        RoundingMode::ToNegativeInfinity,
+    )
+}
+
+/// sd tokens burned for a withdrawal, rounded up
+pub fn calculate_sd_to_burn(
+    amount: Decimal,
+    deposit_balance: Decimal,
+    sd_balance: Decimal,
+) -> Decimal {
+    calculate_s_interest(
+        amount,
+        deposit_balance,
+        sd_balance,
+        RoundingMode::ToPositiveInfinity,
+    )
+}
+
+/// sb tokens minted for a loan, rounded up
+pub fn calculate_sb_to_mint(
+    amount: Decimal,
+    borrow_balance: Decimal,
+    sb_balance: Decimal,
+) -> Decimal {
+    calculate_s_interest(
+        amount,
+        borrow_balance,
+        sb_balance,
+        RoundingMode::ToPositiveInfinity,
+    )
+}
+
+/// sb tokens burned for a repayment, rounded down
+pub fn calculate_sb_to_burn(
+    amount: Decimal,
+    borrow_balance: Decimal,
+    sb_balance: Decimal,
+) -> Decimal {
+    calculate_s_interest(
+        amount,
+        borrow_balance,
+        sb_balance,
+        RoundingMode::ToNegativeInfinity,
+    )
+}
+
+/// Amount of the pool asset sd tokens can be withdrawn for, rounded down
+pub fn calculate_deposit_value(
+    sd_amount: Decimal,
+    deposit_balance: Decimal,
+    sd_balance: Decimal,
+) -> Decimal {
+    calculate_s_value(
+        sd_amount,
+        deposit_balance,
+        sd_balance,
+        RoundingMode::ToNegativeInfinity,
+    )
+}
+
+/// Debt represented by sb tokens, rounded up
+pub fn calculate_debt_value(
+    sb_amount: Decimal,
+    borrow_balance: Decimal,
+    sb_balance: Decimal,
+) -> Decimal {
+    calculate_s_value(
+        sb_amount,
+        borrow_balance,
+        sb_balance,
+        RoundingMode::ToPositiveInfinity,
+    )
+}
+
+/// Share of the LTV-weighted collateral used by the loans, both valued in XRD.
+/// Loans without any collateral are reported as `Decimal::MAX`. Rounded up, so a loan
+/// above the collateral by any amount is above the ratio of 1.
+pub fn calculate_loan_limit_used(collateral: Decimal, loan: Decimal) -> Decimal {
+    if loan == Decimal::ZERO {
+        Decimal::ZERO
+    } else if collateral == Decimal::ZERO {
+        Decimal::MAX
+    } else {
+        round_to_decimal(
+            PreciseDecimal::from(loan) / PreciseDecimal::from(collateral),
+            RoundingMode::ToPositiveInfinity,
+        )
+    }
+}
+
+/// A position can be liquidated once its loan limit used is above the min collateral ratio
+pub fn is_liquidatable(loan_limit_used: Decimal, min_collateral_ratio: Decimal) -> bool {
+    loan_limit_used > min_collateral_ratio
+}
+
+/// How far a position is from liquidation: the liquidation threshold divided by the loan
+/// limit used. The position can be liquidated below 1. `Decimal::MAX` without loans.
+pub fn calculate_health_factor(
+    loan_limit_used: Decimal,
+    liquidation_threshold: Decimal,
+) -> Decimal {
+    if loan_limit_used == Decimal::ZERO {
+        Decimal::MAX
+    } else {
+        liquidation_threshold / loan_limit_used
+    }
+}
+
+/// Drop of all collateral prices, with debt prices unchanged, that makes a position
+/// liquidatable. 1 for positions without loans, 0 for positions already liquidatable.
+pub fn calculate_liquidation_price_drop(health_factor: Decimal) -> Decimal {
+    if health_factor == Decimal::MAX {
+        Decimal::ONE
+    } else if health_factor <= Decimal::ONE {
+        Decimal::ZERO
     } else {
-        let s_price = total / total_s;
-        s_interest = amount / s_price;
+        Decimal::ONE - Decimal::ONE / health_factor
+    }
+}
+
+/// Pool amount that can be borrowed or withdrawn, the pool reserve percent of the deposits
+/// is kept in the pool
+pub fn calculate_available_liquidity(
+    total_deposit: Decimal,
+    total_borrow: Decimal,
+    reserve_balance: Decimal,
+    pool_reserve: Decimal,
+) -> Decimal {
+    total_deposit - reserve_balance - total_deposit * pool_reserve - total_borrow
+}
+
+/// Share of the debt a liquidation can repay. Insolvent positions, whose debt plus
+/// liquidation bonus is above the deposits, can be liquidated in full.
+pub fn calculate_max_liquidation_percent(
+    borrow_in_xrd: Decimal,
+    deposit_in_xrd: Decimal,
+    liquidation_bonus: Decimal,
+    max_liquidation_percent: Decimal,
+) -> Decimal {
+    if borrow_in_xrd * (1 + liquidation_bonus) > deposit_in_xrd {
+        Decimal::ONE
+    } else {
+        max_liquidation_percent
+    }
+}
+
+/// Dutch auction liquidation bonus, ramping up linearly from 0 at the start epoch
+/// to the max bonus after the auction duration
+pub fn calculate_auction_bonus(
+    max_bonus: Decimal,
+    started_at: u64,
+    now: u64,
+    duration: u64,
+) -> Decimal {
+    if now <= started_at {
+        return Decimal::ZERO;
+    }
+    let elapsed = now - started_at;
+    if elapsed >= duration {
+        max_bonus
+    } else {
+        max_bonus * Decimal::from(elapsed) / Decimal::from(duration)
+    }
+}
+
+/// Splits a written off debt into the part covered by the reserve and the part
+/// socialised across depositors
+pub fn calculate_bad_debt_split(bad_debt: Decimal, reserve_balance: Decimal) -> (Decimal, Decimal) {
+    let reserve_loss = bad_debt.min(reserve_balance.max(Decimal::ZERO));
+    (reserve_loss, bad_debt - reserve_loss)
+}
+
+/// Tightest of the limits of an action and the check it comes from, not below 0
+pub fn select_max_amount<C: Copy>(limits: &[(C, Decimal)]) -> (C, Decimal) {
+    let (constraint, amount) = limits.iter().fold(limits[0], |binding, limit| {
+        if limit.1 < binding.1 {
+            *limit
+        } else {
+            binding
+        }
+    });
+    (constraint, amount.max(Decimal::ZERO))
+}
+
+/// Amount a user can still borrow before reaching the max borrow percent of the pool
+pub fn calculate_max_borrow_percent_headroom(
+    deposit_balance: Decimal,
+    max_borrow_percent: Decimal,
+    user_borrow: Decimal,
+) -> Decimal {
+    deposit_balance * max_borrow_percent - user_borrow
+}
+
+/// Amount that can leave the pool before the user's loans are above the max borrow percent
+pub fn calculate_max_borrow_percent_withdraw(
+    deposit_balance: Decimal,
+    max_borrow_percent: Decimal,
+    user_borrow: Decimal,
+) -> Decimal {
+    if user_borrow == Decimal::ZERO {
+        return deposit_balance;
+    }
+    deposit_balance - user_borrow / max_borrow_percent
+}
+
+/// Amount of an asset the LTV-weighted collateral not used by loans can back, values in XRD
+pub fn calculate_borrow_headroom(collateral: Decimal, loan: Decimal, price: Decimal) -> Decimal {
+    (collateral - loan) / price
+}
+
+/// Amount of a collateral asset that can be withdrawn with the loans still covered
+pub fn calculate_withdraw_headroom(
+    collateral: Decimal,
+    loan: Decimal,
+    ltv_ratio: Decimal,
+    price: Decimal,
+) -> Decimal {
+    (collateral - loan) / ltv_ratio / price
+}
+
+/// Bad debt of a pool not made up for by its safety module yet
+pub fn calculate_uncovered_bad_debt(bad_debt: Decimal, bad_debt_covered: Decimal) -> Decimal {
+    (bad_debt - bad_debt_covered).max(Decimal::ZERO)
+}
+
+/// Largest amount a single slash can take from a safety module
+pub fn calculate_max_slash_amount(staked_amount: Decimal, max_slash_percent: Decimal) -> Decimal {
+    staked_amount * max_slash_percent
+}
+
+/// Whether stake tokens are left without any staked asset behind them, new stakes would
+/// then be minted at the first stake price
+pub fn is_safety_module_drained(staked_amount: Decimal, stake_supply: Decimal) -> bool {
+    staked_amount == Decimal::ZERO && stake_supply > Decimal::ZERO
+}
+
+pub fn is_unstake_claimable(current_epoch: u64, claimable_at: u64) -> bool {
+    current_epoch >= claimable_at
+}
+
+fn round_to_decimal(value: PreciseDecimal, rounding_mode: RoundingMode) -> Decimal {
+    value
+        .checked_round(Decimal::SCALE as i32, rounding_mode)
+        .and_then(|rounded| Decimal::try_from(rounded).ok())
+        .expect("Decimal overflow")
+}
+
+/// Change of the amount of each borrowed asset counted against the debt ceiling of each
+/// isolated asset, from the counted loans to the current ones. Loans no longer counted
+/// are released in full.
+pub fn calculate_isolation_debt_changes(
+    counted: &IndexMap<ResourceAddress, IndexMap<ResourceAddress, Decimal>>,
+    current: &IndexMap<ResourceAddress, IndexMap<ResourceAddress, Decimal>>,
+) -> IndexMap<ResourceAddress, IndexMap<ResourceAddress, Decimal>> {
+    let mut changes: IndexMap<ResourceAddress, IndexMap<ResourceAddress, Decimal>> =
+        IndexMap::new();
+    for (isolated_asset_address, loans) in current {
+        let counted_loans = counted.get(isolated_asset_address);
+        for (resource_address, amount) in loans {
+            let counted_amount = counted_loans
+                .and_then(|counted_loans| counted_loans.get(resource_address))
+                .copied()
+                .unwrap_or(Decimal::ZERO);
+            changes
+                .entry(*isolated_asset_address)
+                .or_default()
+                .insert(*resource_address, *amount - counted_amount);
+        }
+    }
+    for (isolated_asset_address, counted_loans) in counted {
+        let loans = current.get(isolated_asset_address);
+        for (resource_address, counted_amount) in counted_loans {
+            if loans.map_or(true, |loans| !loans.contains_key(resource_address)) {
+                changes
+                    .entry(*isolated_asset_address)
+                    .or_default()
+                    .insert(*resource_address, -*counted_amount);
+            }
+        }
     }
-    s_interest
+    changes
 }
@@ -0,0 +1,39 @@
+use scrypto::prelude::*;
+
+/// Category of correlated assets, e.g. stablecoins. Users that opt into the category get its
+/// parameters for collateral in the category and can only borrow assets of the category.
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct EModeCategory {
+    /// Replaces the LTV ratio of the category assets
+    pub ltv_ratio: Decimal,
+    /// Replaces the min collateral ratio of the category assets
+    pub liquidation_threshold: Decimal,
+    /// Replaces the liquidation bonus when collateral in the category is liquidated
+    pub liquidation_bonus: Decimal,
+    pub assets: IndexSet<ResourceAddress>,
+}
+
+impl EModeCategory {
+    pub fn contains(&self, resource_address: ResourceAddress) -> bool {
+        self.assets.contains(&resource_address)
+    }
+
+    pub fn validate(&self) {
+        assert!(
+            self.ltv_ratio > 0.into() && self.ltv_ratio <= 1.into(),
+            "LTV must be between 0.0 and 1.0."
+        );
+        assert!(
+            self.liquidation_threshold > 0.into(),
+            "Liquidation threshold must be greater then 0."
+        );
+        assert!(
+            self.liquidation_bonus >= 0.into(),
+            "Liquidation bonus must not be negative."
+        );
+        assert!(
+            !self.assets.is_empty(),
+            "E-mode category must have at least 1 asset."
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use scrypto::prelude::*;
+
+use crate::e_mode::EModeCategory;
+use crate::pool_parameters::PoolParameters;
+
+/// Kind of loan a borrow or repay event refers to
+#[derive(ScryptoSbor, Clone, Debug, PartialEq, Eq)]
+pub enum LoanType {
+    Variable,
+    Stable,
+    Fixed { loan_id: u64 },
+}
+
+// Events changing a user position carry the pool's sd/sb token prices after the change
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct DepositEvent {
+    pub user_id: NonFungibleLocalId,
+    pub resource_address: ResourceAddress,
+    pub amount: Decimal,
+    pub sd_amount: Decimal,
+    pub sd_price: Decimal,
+    pub sb_price: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct WithdrawEvent {
+    pub user_id: NonFungibleLocalId,
+    pub resource_address: ResourceAddress,
+    pub amount: Decimal,
+    pub sd_amount: Decimal,
+    pub sd_price: Decimal,
+    pub sb_price: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct BorrowEvent {
+    pub user_id: NonFungibleLocalId,
+    pub resource_address: ResourceAddress,
+    pub loan_type: LoanType,
+    pub amount: Decimal,
+    /// sb tokens minted, zero for stable and fixed loans
+    pub sb_amount: Decimal,
+    /// Rate locked by stable and fixed loans, zero for variable loans
+    pub rate: Decimal,
+    pub sd_price: Decimal,
+    pub sb_price: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct RepayEvent {
+    pub user_id: NonFungibleLocalId,
+    pub resource_address: ResourceAddress,
+    pub loan_type: LoanType,
+    pub amount: Decimal,
+    /// sb tokens burned, zero for stable and fixed loans
+    pub sb_amount: Decimal,
+    pub sd_price: Decimal,
+    pub sb_price: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct StableRebalanceEvent {
+    pub user_id: NonFungibleLocalId,
+    pub resource_address: ResourceAddress,
+    pub rate: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct FixedLoanOverdueEvent {
+    pub user_id: NonFungibleLocalId,
+    pub resource_address: ResourceAddress,
+    pub loan_id: u64,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct LiquidationEvent {
+    pub user_id: NonFungibleLocalId,
+    pub repaid_resource_address: ResourceAddress,
+    /// Stable if any stable rate debt was repaid, variable debt is repaid after it
+    pub loan_type: LoanType,
+    pub repaid_amount: Decimal,
+    pub deposit_resource_address: ResourceAddress,
+    /// Deposit asset paid to the liquidator
+    pub reward: Decimal,
+    /// Deposit asset added to the reserve
+    pub platform_bonus: Decimal,
+    /// sd token price of the deposit asset pool
+    pub sd_price: Decimal,
+    /// sb token price of the repaid asset pool
+    pub sb_price: Decimal,
+}
+
+/// Emitted when a position's liquidation auction starts or ends
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct LiquidationAuctionEvent {
+    pub user_id: NonFungibleLocalId,
+    /// Start epoch of the auction, 0 once it ended
+    pub started_at: u64,
+}
+
+/// Debt of a position without collateral written off in a single pool
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct BadDebtEvent {
+    pub user_id: NonFungibleLocalId,
+    pub resource_address: ResourceAddress,
+    /// Loss covered by the reserve
+    pub reserve_loss: Decimal,
+    /// Loss socialised across depositors
+    pub depositors_loss: Decimal,
+    /// Debt written off in the pool since it was created
+    pub bad_debt: Decimal,
+    pub sd_price: Decimal,
+}
+
+/// Emitted when a user opts into an e-mode category, or out of it with category 0
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct EModeEvent {
+    pub user_id: NonFungibleLocalId,
+    pub e_mode_category: u8,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct FlashLoanEvent {
+    pub resource_address: ResourceAddress,
+    pub amount: Decimal,
+    pub fee: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct ReserveCollectionEvent {
+    pub resource_address: ResourceAddress,
+    pub amount: Decimal,
+    pub reserve_balance: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct SkimEvent {
+    pub resource_address: ResourceAddress,
+    pub amount: Decimal,
+    pub to_reserve: bool,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct InterestAccrualEvent {
+    pub resource_address: ResourceAddress,
+    pub borrow_index: Decimal,
+    pub liquidity_index: Decimal,
+    pub reserve_balance: Decimal,
+    pub sd_price: Decimal,
+    pub sb_price: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct PoolCreationEvent {
+    pub resource_address: ResourceAddress,
+    pub pool_component_address: ComponentAddress,
+}
+
+/// Emitted by every admin method changing pool parameters or settings, carries them all
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct PoolParametersUpdateEvent {
+    pub resource_address: ResourceAddress,
+    pub pool_parameters: PoolParameters,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct PoolLockEvent {
+    pub resource_address: ResourceAddress,
+    pub deposit_locked: bool,
+    pub borrow_locked: bool,
+    pub withdraw_locked: bool,
+    pub repay_locked: bool,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct AdminApprovalEvent {
+    pub admin_id: NonFungibleLocalId,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct AdminBlacklistEvent {
+    pub admin_id: NonFungibleLocalId,
+    pub blacklisted: bool,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct AdminBadgeMintEvent {
+    pub admin_id: NonFungibleLocalId,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct EModeCategoryUpdateEvent {
+    pub e_mode_category: u8,
+    pub category: EModeCategory,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct SafetyModuleCreationEvent {
+    pub resource_address: ResourceAddress,
+    pub safety_module_component_address: ComponentAddress,
+}
+
+/// Safety module stake slashed to make up for bad debt of a pool
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct BadDebtCoverEvent {
+    pub resource_address: ResourceAddress,
+    pub amount: Decimal,
+    /// Bad debt of the pool covered by the safety module since the pool was created
+    pub bad_debt_covered: Decimal,
+    pub sd_price: Decimal,
+}
+
+// Safety module events
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct StakeEvent {
+    pub amount: Decimal,
+    pub stake_amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct UnstakeRequestEvent {
+    pub stake_amount: Decimal,
+    pub claimable_at: u64,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct UnstakeClaimEvent {
+    pub amount: Decimal,
+    pub stake_amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct SafetyModuleRewardEvent {
+    pub amount: Decimal,
+    pub staked_amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct SlashEvent {
+    pub amount: Decimal,
+    pub staked_amount: Decimal,
+}
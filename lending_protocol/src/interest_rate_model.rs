@@ -0,0 +1,374 @@
+use scrypto::prelude::*;
+
+use crate::calculations::{
+    calculate_adapted_rate_at_target, calculate_borrow_rate, calculate_utilisation_error,
+};
+
+/// Curve used to derive the borrow rate of a pool from its utilisation
+#[derive(ScryptoSbor, Clone, Debug)]
+pub enum InterestRateModel {
+    /// Two slopes joined at the kink: `base_multiplier` below it and `multiplier` above it
+    LinearKink {
+        base: Decimal,
+        base_multiplier: Decimal,
+        multiplier: Decimal,
+        kink: Decimal,
+    },
+    /// Same slopes as `LinearKink`, with the rate jumping by `jump` once the kink is reached
+    JumpRate {
+        base: Decimal,
+        base_multiplier: Decimal,
+        multiplier: Decimal,
+        kink: Decimal,
+        jump: Decimal,
+    },
+    /// `(utilisation, rate)` points sorted by utilisation, interpolated linearly between them
+    /// and extrapolated with the last segment's slope
+    PiecewiseLinear { points: Vec<(Decimal, Decimal)> },
+    /// Same rate regardless of utilisation
+    Fixed { rate: Decimal },
+    /// Curve around `rate_at_target` that keeps moving toward the target utilisation:
+    /// `rate_at_target` rises while utilisation stays above the target and decays while
+    /// it stays below, at up to `adjustment_speed` per year. The rate at 100% utilisation
+    /// is `curve_steepness` times the rate at target and at 0% it is divided by it.
+    Adaptive {
+        target_utilisation: Decimal,
+        rate_at_target: Decimal,
+        min_rate_at_target: Decimal,
+        max_rate_at_target: Decimal,
+        adjustment_speed: Decimal,
+        curve_steepness: Decimal,
+    },
+}
+
+impl InterestRateModel {
+    pub fn get_borrow_rate(&self, utilisation: Decimal) -> Decimal {
+        match self {
+            InterestRateModel::LinearKink {
+                base,
+                base_multiplier,
+                multiplier,
+                kink,
+            } => calculate_borrow_rate(*multiplier, *base_multiplier, *base, *kink, utilisation),
+            InterestRateModel::JumpRate {
+                base,
+                base_multiplier,
+                multiplier,
+                kink,
+                jump,
+            } => {
+                let borrow_rate =
+                    calculate_borrow_rate(*multiplier, *base_multiplier, *base, *kink, utilisation);
+                if utilisation >= *kink && utilisation > Decimal::ZERO {
+                    borrow_rate + *jump
+                } else {
+                    borrow_rate
+                }
+            }
+            InterestRateModel::PiecewiseLinear { points } => {
+                let mut segment = (points[0], points[1]);
+                for window in points.windows(2) {
+                    segment = (window[0], window[1]);
+                    if utilisation <= window[1].0 {
+                        break;
+                    }
+                }
+                let ((start_utilisation, start_rate), (end_utilisation, end_rate)) = segment;
+                let slope = (end_rate - start_rate) / (end_utilisation - start_utilisation);
+                start_rate + slope * (utilisation - start_utilisation)
+            }
+            InterestRateModel::Fixed { rate } => *rate,
+            InterestRateModel::Adaptive {
+                target_utilisation,
+                rate_at_target,
+                curve_steepness,
+                ..
+            } => {
+                let error = calculate_utilisation_error(utilisation, *target_utilisation);
+                let coefficient = if error < Decimal::ZERO {
+                    Decimal::ONE - Decimal::ONE / *curve_steepness
+                } else {
+                    *curve_steepness - Decimal::ONE
+                };
+                *rate_at_target * (Decimal::ONE + coefficient * error)
+            }
+        }
+    }
+
+    /// Moves the adaptive curve for the time the pool spent at the given utilisation.
+    /// Other models are static.
+    pub fn adapt(&mut self, utilisation: Decimal, elapsed_seconds: i64) {
+        if let InterestRateModel::Adaptive {
+            target_utilisation,
+            rate_at_target,
+            min_rate_at_target,
+            max_rate_at_target,
+            adjustment_speed,
+            ..
+        } = self
+        {
+            *rate_at_target = calculate_adapted_rate_at_target(
+                *rate_at_target,
+                utilisation,
+                *target_utilisation,
+                *adjustment_speed,
+                elapsed_seconds,
+            )
+            .max(*min_rate_at_target)
+            .min(*max_rate_at_target);
+        }
+    }
+
+    pub fn validate(&self) {
+        match self {
+            InterestRateModel::LinearKink {
+                base,
+                base_multiplier,
+                multiplier,
+                kink,
+            } => {
+                Self::validate_kink(*base, *base_multiplier, *multiplier, *kink);
+            }
+            InterestRateModel::JumpRate {
+                base,
+                base_multiplier,
+                multiplier,
+                kink,
+                jump,
+            } => {
+                Self::validate_kink(*base, *base_multiplier, *multiplier, *kink);
+                assert!(*jump >= 0.into(), "Jump must not be negative.");
+            }
+            InterestRateModel::PiecewiseLinear { points } => {
+                assert!(points.len() >= 2, "Curve must have at least 2 points.");
+                assert!(
+                    points[0].0 == Decimal::ZERO,
+                    "Curve must start at 0 utilisation."
+                );
+                for window in points.windows(2) {
+                    assert!(
+                        window[1].0 > window[0].0,
+                        "Curve points must be sorted by utilisation."
+                    );
+                    assert!(
+                        window[1].1 >= window[0].1,
+                        "Curve rates must not decrease."
+                    );
+                }
+                assert!(points[0].1 >= 0.into(), "Rates must not be negative.");
+            }
+            InterestRateModel::Fixed { rate } => {
+                assert!(*rate >= 0.into(), "Rate must not be negative.");
+            }
+            InterestRateModel::Adaptive {
+                target_utilisation,
+                rate_at_target,
+                min_rate_at_target,
+                max_rate_at_target,
+                adjustment_speed,
+                curve_steepness,
+            } => {
+                assert!(
+                    *target_utilisation > 0.into() && *target_utilisation < 1.into(),
+                    "Target utilisation must be between 0.0 and 1.0."
+                );
+                assert!(
+                    *min_rate_at_target >= 0.into() && *min_rate_at_target <= *max_rate_at_target,
+                    "Min rate at target must be between 0 and max rate at target."
+                );
+                assert!(
+                    *rate_at_target >= *min_rate_at_target
+                        && *rate_at_target <= *max_rate_at_target,
+                    "Rate at target must be between min and max rate at target."
+                );
+                assert!(
+                    *adjustment_speed >= 0.into(),
+                    "Adjustment speed must not be negative."
+                );
+                assert!(
+                    *curve_steepness >= 1.into(),
+                    "Curve steepness must be at least 1."
+                );
+            }
+        }
+    }
+
+    fn validate_kink(base: Decimal, base_multiplier: Decimal, multiplier: Decimal, kink: Decimal) {
+        assert!(base >= 0.into(), "Base must not be negative.");
+        assert!(multiplier > 0.into(), "Multiplier must be greater then 0.");
+        assert!(
+            multiplier > base_multiplier,
+            "Multiplier must be greater then Base Multiplier."
+        );
+        assert!(
+            kink >= 0.into() && kink <= 100.into(),
+            "Kink must be between 0 and 100."
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YEAR: i64 = 365 * 24 * 60 * 60;
+
+    fn adaptive(rate_at_target: Decimal) -> InterestRateModel {
+        InterestRateModel::Adaptive {
+            target_utilisation: dec!("0.8"),
+            rate_at_target,
+            min_rate_at_target: dec!("0.001"),
+            max_rate_at_target: dec!("2"),
+            adjustment_speed: dec!("50"),
+            curve_steepness: dec!("4"),
+        }
+    }
+
+    fn get_rate_at_target(model: &InterestRateModel) -> Decimal {
+        match model {
+            InterestRateModel::Adaptive { rate_at_target, .. } => *rate_at_target,
+            _ => panic!("Not an adaptive model"),
+        }
+    }
+
+    #[test]
+    fn test_jump_rate_jumps_at_the_kink() {
+        let model = InterestRateModel::JumpRate {
+            base: Decimal::ZERO,
+            base_multiplier: dec!("0.1"),
+            multiplier: Decimal::ONE,
+            kink: dec!("0.8"),
+            jump: dec!("0.5"),
+        };
+        model.validate();
+
+        assert_eq!(model.get_borrow_rate(Decimal::ZERO), Decimal::ZERO);
+        assert_eq!(model.get_borrow_rate(dec!("0.5")), dec!("0.05"));
+        assert_eq!(model.get_borrow_rate(dec!("0.8")), dec!("0.58"));
+        assert_eq!(model.get_borrow_rate(dec!("0.9")), dec!("0.68"));
+    }
+
+    #[test]
+    fn test_piecewise_linear_interpolates_between_points() {
+        let model = InterestRateModel::PiecewiseLinear {
+            points: vec![
+                (Decimal::ZERO, Decimal::ZERO),
+                (dec!("0.5"), dec!("0.05")),
+                (dec!("0.75"), dec!("0.1")),
+            ],
+        };
+        model.validate();
+
+        assert_eq!(model.get_borrow_rate(dec!("0.25")), dec!("0.025"));
+        assert_eq!(model.get_borrow_rate(dec!("0.5")), dec!("0.05"));
+        assert_eq!(model.get_borrow_rate(dec!("0.6")), dec!("0.07"));
+        assert_eq!(model.get_borrow_rate(dec!("0.75")), dec!("0.1"));
+    }
+
+    #[test]
+    fn test_piecewise_linear_extrapolates_past_the_last_point() {
+        let model = InterestRateModel::PiecewiseLinear {
+            points: vec![
+                (Decimal::ZERO, Decimal::ZERO),
+                (dec!("0.5"), dec!("0.05")),
+                (dec!("0.75"), dec!("0.1")),
+            ],
+        };
+
+        assert_eq!(model.get_borrow_rate(Decimal::ONE), dec!("0.15"));
+    }
+
+    #[test]
+    fn test_adaptive_rate_below_and_above_target() {
+        let model = adaptive(dec!("0.04"));
+        model.validate();
+
+        assert_eq!(model.get_borrow_rate(dec!("0.8")), dec!("0.04"));
+        // Below the target the curve flattens toward rate at target / steepness
+        assert_eq!(model.get_borrow_rate(dec!("0.4")), dec!("0.025"));
+        assert_eq!(model.get_borrow_rate(Decimal::ZERO), dec!("0.01"));
+        // Above the target it steepens toward rate at target * steepness
+        assert_eq!(model.get_borrow_rate(dec!("0.9")), dec!("0.1"));
+        assert_eq!(model.get_borrow_rate(Decimal::ONE), dec!("0.16"));
+    }
+
+    #[test]
+    fn test_adapt_is_clamped_to_the_max_rate_at_target() {
+        let mut model = adaptive(dec!("0.04"));
+        model.adapt(Decimal::ONE, YEAR);
+
+        assert_eq!(get_rate_at_target(&model), dec!("2"));
+    }
+
+    #[test]
+    fn test_adapt_is_clamped_to_the_min_rate_at_target() {
+        let mut model = adaptive(dec!("0.04"));
+        model.adapt(Decimal::ZERO, YEAR);
+
+        assert_eq!(get_rate_at_target(&model), dec!("0.001"));
+    }
+
+    #[test]
+    fn test_adapt_leaves_static_models_unchanged() {
+        let mut model = InterestRateModel::Fixed { rate: dec!("0.05") };
+        model.adapt(Decimal::ONE, YEAR);
+
+        assert_eq!(model.get_borrow_rate(Decimal::ONE), dec!("0.05"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Target utilisation must be between 0.0 and 1.0.")]
+    fn test_adaptive_target_utilisation_must_be_below_one() {
+        InterestRateModel::Adaptive {
+            target_utilisation: Decimal::ONE,
+            rate_at_target: dec!("0.04"),
+            min_rate_at_target: dec!("0.001"),
+            max_rate_at_target: dec!("2"),
+            adjustment_speed: dec!("50"),
+            curve_steepness: dec!("4"),
+        }
+        .validate();
+    }
+
+    #[test]
+    #[should_panic(expected = "Rate at target must be between min and max rate at target.")]
+    fn test_adaptive_rate_at_target_must_be_within_bounds() {
+        adaptive(dec!("3")).validate();
+    }
+
+    #[test]
+    #[should_panic(expected = "Curve steepness must be at least 1.")]
+    fn test_adaptive_curve_steepness_must_be_at_least_one() {
+        InterestRateModel::Adaptive {
+            target_utilisation: dec!("0.8"),
+            rate_at_target: dec!("0.04"),
+            min_rate_at_target: dec!("0.001"),
+            max_rate_at_target: dec!("2"),
+            adjustment_speed: dec!("50"),
+            curve_steepness: dec!("0.5"),
+        }
+        .validate();
+    }
+
+    #[test]
+    #[should_panic(expected = "Curve rates must not decrease.")]
+    fn test_piecewise_linear_rates_must_not_decrease() {
+        InterestRateModel::PiecewiseLinear {
+            points: vec![(Decimal::ZERO, dec!("0.1")), (Decimal::ONE, dec!("0.05"))],
+        }
+        .validate();
+    }
+
+    #[test]
+    #[should_panic(expected = "Jump must not be negative.")]
+    fn test_jump_must_not_be_negative() {
+        InterestRateModel::JumpRate {
+            base: Decimal::ZERO,
+            base_multiplier: dec!("0.1"),
+            multiplier: Decimal::ONE,
+            kink: dec!("0.8"),
+            jump: dec!("-0.1"),
+        }
+        .validate();
+    }
+}
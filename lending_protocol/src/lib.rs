@@ -1,5 +1,9 @@
-mod calculations;
+pub mod calculations;
+mod e_mode;
+mod events;
+mod interest_rate_model;
 mod pool_parameters;
 mod protocol;
 mod resources;
+mod safety_module;
 mod user;
@@ -1,5 +1,8 @@
 use scrypto::prelude::*;
 
+use crate::calculations::*;
+use crate::interest_rate_model::InterestRateModel;
+
 #[derive(ScryptoSbor, Clone)]
 pub struct PoolParameters {
     pub min_collateral_ratio: Decimal,
@@ -11,16 +14,10 @@ pub struct PoolParameters {
     pub liquidation_bonus: Decimal,
     //Loan to value ratio
     pub ltv_ratio: Decimal,
-    // Multipliers for the assets
-    pub multiplier: Decimal,
-    // Base multipliers for the assets
-    pub base_multiplier: Decimal,
-    // Bases for the assets
-    pub base: Decimal,
+    // Curve deriving the borrow rate from utilisation
+    pub interest_rate_model: InterestRateModel,
     // Reserve factors for the assets
     pub reserve_factor: Decimal,
-    // KINKs for the assets
-    pub kink: Decimal,
     // Per asset/editable
     pub liquidation_reserve_factor: Decimal,
     //Deposit lock state (true if locked)
@@ -35,6 +32,8 @@ pub struct PoolParameters {
     pub pool_reserve: Decimal,
     //Pool deposit limit in USD
     pub deposit_limit: Decimal,
+    // Max total borrow balance of the pool, 0 for no cap
+    pub borrow_cap: Decimal,
     // Loan balances
     pub borrow_balance: Decimal,
     // Deposit balances
@@ -43,8 +42,103 @@ pub struct PoolParameters {
     pub reserve_balance: Decimal,
     pub sd_balance: Decimal,
     pub sb_balance: Decimal,
+    // Tokens in the Pool component vault, counted by the protocol on every take and put
+    pub vault_balance: Decimal,
     pub balances_updated_at: u64,
+    // Proposer timestamp (seconds) of the last interest accrual
+    pub last_accrued_at: i64,
+    // Cumulative borrow index, grows with every borrow interest accrual
+    pub borrow_index: Decimal,
+    // Cumulative liquidity index, grows with every deposit interest accrual
+    pub liquidity_index: Decimal,
+    // Stable rate loan balances
+    pub stable_borrow_balance: Decimal,
+    // Stable rates weighted by stable loan balances
+    pub average_stable_rate: Decimal,
+    //Stable borrow lock state (true if locked)
+    pub stable_borrow_locked: bool,
+    // Premium over the variable rate locked by new stable rate loans
+    pub stable_rate_premium: Decimal,
+    // Utilisation above which stable rate loans can be re-priced
+    pub stable_rebalance_utilisation: Decimal,
+    // Seconds utilisation has to stay above the rebalance utilisation before re-pricing
+    pub stable_rebalance_min_duration: i64,
+    // Timestamp (seconds) utilisation went above the rebalance utilisation, 0 while below
+    pub stable_rebalance_since: i64,
+    // Principal of fixed-term loans not repaid yet
+    pub fixed_borrow_balance: Decimal,
+    //Fixed-term borrow lock state (true if locked)
+    pub fixed_borrow_locked: bool,
+    // Rate locked by new fixed-term loans
+    pub fixed_loan_rate: Decimal,
+    // Rate added to the loan rate after maturity, locked by new fixed-term loans
+    pub fixed_loan_penalty_rate: Decimal,
+    // Longest fixed-term loan in epochs
+    pub fixed_loan_max_term: u64,
+    // Fee charged on flash loans
+    pub flash_loan_fee: Decimal,
+    // Flash loans taken in the current transaction and not repaid yet
+    pub flash_loan_balance: Decimal,
+    //Dutch auction liquidation mode (true if enabled)
+    pub liquidation_auction: bool,
+    // Bonus the auction ramps up to
+    pub max_liquidation_bonus: Decimal,
+    // Epochs the auction bonus takes to reach the max
+    pub liquidation_auction_duration: u64,
+    // Debt written off since the pool was created
+    pub bad_debt: Decimal,
+    // Bad debt made up for by slashing the safety module
+    pub bad_debt_covered: Decimal,
+    // Part of the collected reserve sent to the safety module
+    pub safety_module_share: Decimal,
+    //Isolation mode (true if isolated), users with the asset as collateral can only
+    //borrow assets borrowable in isolation
+    pub isolated: bool,
+    // Max XRD value of loans backed by the asset while isolated
+    pub isolation_debt_ceiling: Decimal,
+    // Loans backed by the asset, in units of each borrowed asset, counted when the
+    // loans of a user change
+    pub isolation_debt: IndexMap<ResourceAddress, Decimal>,
+    // Whether users with isolated collateral can borrow the asset
+    pub borrowable_in_isolation: bool,
+}
+
+/// Difference between vault and ledger small enough to come from rounding
+pub const POOL_DEFICIT_TOLERANCE: Decimal = dec!("0.000000000001");
+
+/// Current rates and exchange rates of a pool
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct PoolRates {
+    pub utilisation: Decimal,
+    pub borrow_rate: Decimal,
+    // Rate a new stable rate loan would lock
+    pub stable_rate: Decimal,
+    // Borrow rate earned by depositors, net of the reserve factor
+    pub supply_rate: Decimal,
+    pub borrow_apy: Decimal,
+    pub supply_apy: Decimal,
+    pub sd_price: Decimal,
+    pub sb_price: Decimal,
+    // Variable, stable and fixed-term loans of the pool
+    pub total_borrow: Decimal,
+    // Max total borrow balance, 0 for no cap
+    pub borrow_cap: Decimal,
 }
+
+/// Risk parameters of a pool set by `update_pool_parameters`
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct PoolRiskParameters {
+    pub liquidation_reserve_factor: Decimal,
+    pub liquidation_bonus: Decimal,
+    pub max_liquidation_percent: Decimal,
+    pub max_borrow_percent: Decimal,
+    pub min_collateral_ratio: Decimal,
+    pub pool_reserve: Decimal,
+    pub deposit_limit: Decimal,
+    // Max total borrow balance, 0 for no cap
+    pub borrow_cap: Decimal,
+}
+
 impl PoolParameters {
     pub fn _get_pool_parameters(&mut self) -> (Decimal, Decimal, Decimal, Decimal) {
         (
@@ -55,38 +149,237 @@ impl PoolParameters {
         )
     }
 
-    pub fn update_pool_parameters(
+    /// Accrues interest since the last update into the pool balances and indexes.
+    /// Must be called before any operation that reads or changes the balances.
+    pub fn accrue_interest(&mut self) {
+        let now = get_current_time();
+        let elapsed = now - self.last_accrued_at;
+        let utilisation = get_utilisation(self.deposit_balance, self.get_total_borrow_balance());
+        let borrow_rate = self.interest_rate_model.get_borrow_rate(utilisation);
+        let borrow_apr = calculate_compounded_rate(borrow_rate, elapsed);
+        let (borrow_interest, reserve_interest, deposit_interest) =
+            calculate_interests(self.borrow_balance, borrow_apr, self.reserve_factor);
+        let stable_borrow_apr = calculate_compounded_rate(self.average_stable_rate, elapsed);
+        let (stable_borrow_interest, stable_reserve_interest, stable_deposit_interest) =
+            calculate_interests(
+                self.stable_borrow_balance,
+                stable_borrow_apr,
+                self.reserve_factor,
+            );
+        let deposit_interest = deposit_interest + stable_deposit_interest;
+
+        self.borrow_index = calculate_index(self.borrow_index, borrow_apr);
+        if self.deposit_balance != Decimal::ZERO {
+            let deposit_apr = deposit_interest / self.deposit_balance;
+            self.liquidity_index = calculate_index(self.liquidity_index, deposit_apr);
+        }
+        self.borrow_balance += borrow_interest;
+        self.stable_borrow_balance += stable_borrow_interest;
+        self.reserve_balance += reserve_interest + stable_reserve_interest;
+        self.deposit_balance += deposit_interest;
+        self.interest_rate_model.adapt(utilisation, elapsed);
+        self.stable_rebalance_since = calculate_stable_rebalance_since(
+            self.stable_rebalance_since,
+            self.last_accrued_at,
+            utilisation,
+            self.stable_rebalance_utilisation,
+        );
+        self.last_accrued_at = now;
+    }
+
+    /// Rates for the current balances, projected over a year at the current utilisation
+    pub fn get_pool_rates(&self) -> PoolRates {
+        let utilisation = get_utilisation(self.deposit_balance, self.get_total_borrow_balance());
+        let variable_utilisation = get_utilisation(self.deposit_balance, self.borrow_balance);
+        let stable_utilisation = get_utilisation(self.deposit_balance, self.stable_borrow_balance);
+        let borrow_rate = self.interest_rate_model.get_borrow_rate(utilisation);
+        let borrow_apy = calculate_compounded_rate(borrow_rate, SECONDS_PER_YEAR);
+        let stable_borrow_apy =
+            calculate_compounded_rate(self.average_stable_rate, SECONDS_PER_YEAR);
+        let depositors_share = Decimal::ONE - self.reserve_factor;
+        let (sd_price, sb_price) = self.get_token_prices();
+        PoolRates {
+            utilisation,
+            borrow_rate,
+            stable_rate: borrow_rate + self.stable_rate_premium,
+            supply_rate: (borrow_rate * variable_utilisation
+                + self.average_stable_rate * stable_utilisation)
+                * depositors_share,
+            borrow_apy,
+            supply_apy: (borrow_apy * variable_utilisation
+                + stable_borrow_apy * stable_utilisation)
+                * depositors_share,
+            sd_price,
+            sb_price,
+            total_borrow: self.get_total_borrow_balance(),
+            borrow_cap: self.borrow_cap,
+        }
+    }
+
+    /// Prices of the sd and sb tokens in the pool asset
+    pub fn get_token_prices(&self) -> (Decimal, Decimal) {
+        (
+            calculate_token_price(self.deposit_balance, self.sd_balance),
+            calculate_token_price(self.borrow_balance, self.sb_balance),
+        )
+    }
+
+    pub fn get_total_borrow_balance(&self) -> Decimal {
+        self.borrow_balance + self.stable_borrow_balance + self.fixed_borrow_balance
+    }
+
+    /// Amount that can still be borrowed under the borrow cap, None without a cap
+    pub fn get_remaining_borrow_cap(&self) -> Option<Decimal> {
+        if self.borrow_cap == Decimal::ZERO {
+            return None;
+        }
+        Some(self.borrow_cap - self.get_total_borrow_balance())
+    }
+
+    /// Rate a new stable rate loan locks at the current utilisation
+    pub fn get_stable_rate(&self) -> Decimal {
+        let utilisation = get_utilisation(self.deposit_balance, self.get_total_borrow_balance());
+        self.interest_rate_model.get_borrow_rate(utilisation) + self.stable_rate_premium
+    }
+
+    pub fn on_stable_borrow(&mut self, amount: Decimal, rate: Decimal) {
+        let stable_borrow_balance = self.stable_borrow_balance + amount;
+        if stable_borrow_balance == Decimal::ZERO {
+            return;
+        }
+        self.average_stable_rate = (self.stable_borrow_balance * self.average_stable_rate
+            + amount * rate)
+            / stable_borrow_balance;
+        self.stable_borrow_balance = stable_borrow_balance;
+    }
+
+    pub fn on_stable_repay(&mut self, amount: Decimal, rate: Decimal) {
+        // User balances accrue at their own rate, the pool total can end up slightly lower
+        if amount >= self.stable_borrow_balance {
+            self.stable_borrow_balance = Decimal::ZERO;
+            self.average_stable_rate = Decimal::ZERO;
+            return;
+        }
+        let stable_borrow_balance = self.stable_borrow_balance - amount;
+        let average_stable_rate = (self.stable_borrow_balance * self.average_stable_rate
+            - amount * rate)
+            / stable_borrow_balance;
+        self.average_stable_rate = average_stable_rate.max(Decimal::ZERO);
+        self.stable_borrow_balance = stable_borrow_balance;
+    }
+
+    /// Amount the pool vault should hold according to the balances
+    pub fn get_expected_vault_amount(&self) -> Decimal {
+        self.deposit_balance + self.reserve_balance
+            - self.get_total_borrow_balance()
+            - self.flash_loan_balance
+    }
+
+    /// Surplus (positive) or deficit (negative) of the vault against the balances
+    pub fn get_vault_delta(&self) -> Decimal {
+        self.vault_balance - self.get_expected_vault_amount()
+    }
+
+    pub fn assert_no_deficit(&self) {
+        let delta = self.get_vault_delta();
+        if delta < -POOL_DEFICIT_TOLERANCE {
+            panic!("Pool vault deficit of {}", -delta);
+        }
+    }
+
+    pub fn on_fixed_borrow(&mut self, amount: Decimal) {
+        self.fixed_borrow_balance += amount;
+    }
+
+    /// Fixed-term loans accrue on the user side only, their interest is split when repaid
+    pub fn on_fixed_repay(&mut self, principal_repaid: Decimal, interest_repaid: Decimal) {
+        self.fixed_borrow_balance =
+            (self.fixed_borrow_balance - principal_repaid).max(Decimal::ZERO);
+        let reserve_interest = interest_repaid * self.reserve_factor;
+        self.reserve_balance += reserve_interest;
+        self.deposit_balance += interest_repaid - reserve_interest;
+    }
+
+    /// Writes off a variable rate debt. Returns the loss taken from the reserve and
+    /// from depositors.
+    pub fn on_bad_debt_borrow(&mut self, sb_amount: Decimal) -> (Decimal, Decimal) {
+        let debt = calculate_debt_value(sb_amount, self.borrow_balance, self.sb_balance)
+            .min(self.borrow_balance);
+        self.borrow_balance -= debt;
+        self.sb_balance = (self.sb_balance - sb_amount).max(Decimal::ZERO);
+        self.write_off(debt)
+    }
+
+    /// Writes off a stable rate debt
+    pub fn on_bad_debt_stable_borrow(
         &mut self,
-        liquidation_reserve_factor: Decimal,
-        liquidation_bonus: Decimal,
-        max_liquidation_percent: Decimal,
-        max_borrow_percent: Decimal,
-        min_collateral_ratio: Decimal,
-        pool_reserve: Decimal,
-        pool_deposit_limit: Decimal,
-    ) {
-        self.liquidation_reserve_factor = liquidation_reserve_factor;
-        self.liquidation_bonus = liquidation_bonus;
-        self.max_liquidation_percent = max_liquidation_percent;
-        self.max_borrow_percent = max_borrow_percent;
-        self.min_collateral_ratio = min_collateral_ratio;
-        self.pool_reserve = pool_reserve;
-        self.deposit_limit = pool_deposit_limit;
+        amount: Decimal,
+        rate: Decimal,
+    ) -> (Decimal, Decimal) {
+        let stable_borrow_balance = self.stable_borrow_balance;
+        self.on_stable_repay(amount, rate);
+        self.write_off(stable_borrow_balance - self.stable_borrow_balance)
+    }
+
+    /// Writes off the principal of a fixed-term loan, its interest was never accounted
+    pub fn on_bad_debt_fixed_loan(&mut self, principal: Decimal) -> (Decimal, Decimal) {
+        let debt = principal.min(self.fixed_borrow_balance);
+        self.fixed_borrow_balance -= debt;
+        self.write_off(debt)
+    }
+
+    // The loss is taken from the reserve first, the rest lowers the sd token price
+    fn write_off(&mut self, debt: Decimal) -> (Decimal, Decimal) {
+        let (reserve_loss, depositors_loss) = calculate_bad_debt_split(debt, self.reserve_balance);
+        self.reserve_balance -= reserve_loss;
+        self.deposit_balance -= depositors_loss;
+        self.bad_debt += debt;
+        (reserve_loss, depositors_loss)
+    }
+
+    /// Splits the flash loan fee between depositors and reserve like interest
+    pub fn on_flash_loan_repay(&mut self, amount: Decimal, fee: Decimal) {
+        self.flash_loan_balance -= amount;
+        let reserve_fee = fee * self.reserve_factor;
+        self.reserve_balance += reserve_fee;
+        self.deposit_balance += fee - reserve_fee;
+    }
+
+    pub fn update_pool_parameters(&mut self, parameters: PoolRiskParameters) {
+        self.liquidation_reserve_factor = parameters.liquidation_reserve_factor;
+        self.liquidation_bonus = parameters.liquidation_bonus;
+        self.max_liquidation_percent = parameters.max_liquidation_percent;
+        self.max_borrow_percent = parameters.max_borrow_percent;
+        self.min_collateral_ratio = parameters.min_collateral_ratio;
+        self.pool_reserve = parameters.pool_reserve;
+        self.deposit_limit = parameters.deposit_limit;
+        self.borrow_cap = parameters.borrow_cap;
+    }
+
+    /// Liquidation bonus for a position whose auction started at the given epoch,
+    /// 0 if it has not started. Without auction mode the bonus is fixed.
+    pub fn get_liquidation_bonus(&self, auction_started_at: u64) -> Decimal {
+        if !self.liquidation_auction {
+            return self.liquidation_bonus;
+        }
+        if auction_started_at == 0 {
+            return Decimal::ZERO;
+        }
+        calculate_auction_bonus(
+            self.max_liquidation_bonus,
+            auction_started_at,
+            Runtime::current_epoch().number(),
+            self.liquidation_auction_duration,
+        )
     }
 
     pub fn update_pool_settings(
         &mut self,
-        base: Decimal,
-        base_multiplier: Decimal,
-        multiplier: Decimal,
-        kink: Decimal,
+        interest_rate_model: InterestRateModel,
         reserve_factor: Decimal,
         ltv_ratio: Decimal,
     ) {
-        self.base = base;
-        self.base_multiplier = base_multiplier;
-        self.multiplier = multiplier;
-        self.kink = kink;
+        self.interest_rate_model = interest_rate_model;
         self.reserve_factor = reserve_factor;
         self.ltv_ratio = ltv_ratio;
     }
@@ -107,6 +400,84 @@ impl PoolParameters {
         self.balances_updated_at = Runtime::current_epoch().number();
     }
 
+    pub fn update_stable_borrow_settings(
+        &mut self,
+        stable_borrow_locked: bool,
+        stable_rate_premium: Decimal,
+    ) {
+        self.stable_borrow_locked = stable_borrow_locked;
+        self.stable_rate_premium = stable_rate_premium;
+    }
+
+    pub fn update_stable_rebalance_settings(
+        &mut self,
+        stable_rebalance_utilisation: Decimal,
+        stable_rebalance_min_duration: i64,
+    ) {
+        self.stable_rebalance_utilisation = stable_rebalance_utilisation;
+        self.stable_rebalance_min_duration = stable_rebalance_min_duration;
+        // Time above the previous threshold does not count toward the new one
+        self.stable_rebalance_since = 0;
+    }
+
+    pub fn update_fixed_loan_settings(
+        &mut self,
+        fixed_borrow_locked: bool,
+        fixed_loan_rate: Decimal,
+        fixed_loan_penalty_rate: Decimal,
+        fixed_loan_max_term: u64,
+    ) {
+        self.fixed_borrow_locked = fixed_borrow_locked;
+        self.fixed_loan_rate = fixed_loan_rate;
+        self.fixed_loan_penalty_rate = fixed_loan_penalty_rate;
+        self.fixed_loan_max_term = fixed_loan_max_term;
+    }
+
+    pub fn update_liquidation_auction_settings(
+        &mut self,
+        liquidation_auction: bool,
+        max_liquidation_bonus: Decimal,
+        liquidation_auction_duration: u64,
+    ) {
+        self.liquidation_auction = liquidation_auction;
+        self.max_liquidation_bonus = max_liquidation_bonus;
+        self.liquidation_auction_duration = liquidation_auction_duration;
+    }
+
+    pub fn update_isolation_settings(
+        &mut self,
+        isolated: bool,
+        isolation_debt_ceiling: Decimal,
+        borrowable_in_isolation: bool,
+    ) {
+        self.isolated = isolated;
+        self.isolation_debt_ceiling = isolation_debt_ceiling;
+        self.borrowable_in_isolation = borrowable_in_isolation;
+    }
+
+    pub fn on_isolation_debt_change(&mut self, resource_address: ResourceAddress, change: Decimal) {
+        let debt = self
+            .isolation_debt
+            .get(&resource_address)
+            .copied()
+            .unwrap_or(Decimal::ZERO);
+        let debt = (debt + change).max(Decimal::ZERO);
+        if debt > Decimal::ZERO {
+            self.isolation_debt.insert(resource_address, debt);
+        } else {
+            self.isolation_debt.shift_remove(&resource_address);
+        }
+    }
+
+    /// XRD value of the loans backed by the asset at the current prices
+    pub fn get_isolation_debt_value(&self, prices: &HashMap<ResourceAddress, Decimal>) -> Decimal {
+        self.isolation_debt
+            .iter()
+            .fold(Decimal::ZERO, |value, (resource_address, amount)| {
+                value + *amount * *prices.get(resource_address).unwrap()
+            })
+    }
+
     pub fn lock_pool(
         &mut self,
         deposit_locked: bool,
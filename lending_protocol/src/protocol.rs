@@ -1,11 +1,43 @@
 use crate::calculations::*;
+use crate::events::*;
+use crate::pool_parameters::PoolRiskParameters;
 use crate::resources::*;
-use crate::user::UserData;
+use crate::safety_module::safety_module::{SafetyModule, SafetyModuleFunctions};
+use crate::user::{
+    LimitConstraint, LiquidationError, LiquidationSimulation, MaxAmount, UserData, UserHealth,
+};
 use scrypto::prelude::*;
 
 #[blueprint]
+#[events(
+    DepositEvent,
+    WithdrawEvent,
+    BorrowEvent,
+    RepayEvent,
+    StableRebalanceEvent,
+    FixedLoanOverdueEvent,
+    LiquidationEvent,
+    LiquidationAuctionEvent,
+    BadDebtEvent,
+    SafetyModuleCreationEvent,
+    BadDebtCoverEvent,
+    FlashLoanEvent,
+    ReserveCollectionEvent,
+    SkimEvent,
+    InterestAccrualEvent,
+    PoolCreationEvent,
+    PoolParametersUpdateEvent,
+    PoolLockEvent,
+    AdminApprovalEvent,
+    AdminBlacklistEvent,
+    AdminBadgeMintEvent,
+    EModeCategoryUpdateEvent,
+    EModeEvent
+)]
 mod lending_protocol {
-    use crate::pool_parameters::PoolParameters;
+    use crate::e_mode::EModeCategory;
+    use crate::interest_rate_model::InterestRateModel;
+    use crate::pool_parameters::{PoolParameters, PoolRates};
 
     extern_blueprint! {
     // import the Pool package from the ledger using its package address
@@ -53,15 +85,47 @@ mod lending_protocol {
             withdraw =>  PUBLIC;
             borrow =>  PUBLIC;
             repay => PUBLIC;
-            liquidate => restrict_to: [admin];
+            borrow_stable => PUBLIC;
+            repay_stable => PUBLIC;
+            rebalance_stable_borrow => PUBLIC;
+            borrow_fixed => PUBLIC;
+            repay_fixed => PUBLIC;
+            mark_fixed_loan_overdue => PUBLIC;
+            flash_loan => PUBLIC;
+            repay_flash_loan => PUBLIC;
+            liquidate => PUBLIC;
+            liquidate_fixed_loan => PUBLIC;
+            update_liquidation_auction => PUBLIC;
+            resolve_bad_debt => PUBLIC;
+            set_e_mode => PUBLIC;
             collect_reserve_balance => restrict_to: [admin];
             insert_pool_component =>  restrict_to: [admin];
             update_pool_parameters => restrict_to: [admin];
-            update_balances => restrict_to: [admin];
             update_pool_settings => restrict_to: [admin];
+            update_stable_borrow_settings => restrict_to: [admin];
+            update_stable_rebalance_settings => restrict_to: [admin];
+            update_fixed_loan_settings => restrict_to: [admin];
+            update_flash_loan_fee => restrict_to: [admin];
+            update_liquidation_auction_settings => restrict_to: [admin];
+            update_isolation_settings => restrict_to: [admin];
+            create_safety_module => restrict_to: [admin];
+            update_safety_module_settings => restrict_to: [admin];
+            cover_bad_debt => restrict_to: [admin];
+            update_e_mode_category => restrict_to: [admin];
+            skim => restrict_to: [admin];
             lock_pool => restrict_to: [admin];
             add_to_blacklist => restrict_to: [admin];
             remove_from_blacklist => restrict_to: [admin];
+            get_pool_rates => PUBLIC;
+            get_all_pool_rates => PUBLIC;
+            get_e_mode_category => PUBLIC;
+            get_user_health => PUBLIC;
+            simulate_liquidation => PUBLIC;
+            preview_max_borrow => PUBLIC;
+            preview_max_withdraw => PUBLIC;
+            accrue_interest => PUBLIC;
+            accrue_all => PUBLIC;
+            reconcile => PUBLIC;
         }
     }
 
@@ -76,9 +140,12 @@ mod lending_protocol {
         admin_badge_id_counter: u64,
         admin_badge_address: ResourceAddress,
         user_resource_manager: NonFungibleResourceManager,
+        flash_loan_receipt_manager: NonFungibleResourceManager,
         pool_parameters: KeyValueStore<ResourceAddress, PoolParameters>,
         ltv_ratios: HashMap<ResourceAddress, Decimal>,
         admin_blacklist: HashSet<NonFungibleLocalId>,
+        safety_modules: KeyValueStore<ResourceAddress, Global<SafetyModule>>,
+        e_mode_categories: KeyValueStore<u8, EModeCategory>,
     }
 
     impl LendingProtocol {
@@ -109,11 +176,17 @@ mod lending_protocol {
                 component_rule.clone(),
                 admin_rule.clone(),
             );
+            let flash_loan_receipt_manager = create_flash_loan_receipt_manager(
+                protocol_rule.clone(),
+                component_rule.clone(),
+                admin_rule.clone(),
+            );
 
             Self {
                 protocol_badge: NonFungibleVault::with_bucket(protocol_badge),
                 pools: KeyValueStore::new(),
                 user_resource_manager,
+                flash_loan_receipt_manager,
                 admin_rule: admin_rule.clone(),
                 component_rule: component_rule.clone(),
                 protocol_rule: protocol_rule,
@@ -124,6 +197,8 @@ mod lending_protocol {
                 oracle_address,
                 ltv_ratios: HashMap::new(),
                 admin_blacklist: HashSet::new(),
+                safety_modules: KeyValueStore::new(),
+                e_mode_categories: KeyValueStore::new(),
             }
             .instantiate()
             .prepare_to_globalize(OwnerRole::None)
@@ -158,6 +233,11 @@ mod lending_protocol {
             let protocol_rule: AccessRule = rule!(require(protocol_badge.resource_address()));
             let user_resource_manager: NonFungibleResourceManager = user_badge_address.into();
             let admin_resource_manager: NonFungibleResourceManager = admin_badge_address.into();
+            let flash_loan_receipt_manager = create_flash_loan_receipt_manager(
+                protocol_rule.clone(),
+                component_rule.clone(),
+                admin_rule.clone(),
+            );
             let non_fungible_local_ids: IndexSet<NonFungibleLocalId> =
                 protocol_badge.non_fungible_local_ids();
             protocol_badge.authorize_with_non_fungibles(&non_fungible_local_ids, || {
@@ -172,6 +252,7 @@ mod lending_protocol {
                 protocol_badge: NonFungibleVault::with_bucket(protocol_badge),
                 pools: KeyValueStore::new(),
                 user_resource_manager,
+                flash_loan_receipt_manager,
                 admin_rule: admin_rule.clone(),
                 component_rule: component_rule.clone(),
                 admin_signature_check: HashMap::new(),
@@ -182,6 +263,8 @@ mod lending_protocol {
                 oracle_address,
                 ltv_ratios: HashMap::new(),
                 admin_blacklist: HashSet::new(),
+                safety_modules: KeyValueStore::new(),
+                e_mode_categories: KeyValueStore::new(),
             }
             .instantiate()
             .prepare_to_globalize(OwnerRole::None)
@@ -220,31 +303,30 @@ mod lending_protocol {
                 ltv_ratio >= 0.into() && ltv_ratio <= 1.into(),
                 "LTV must be between 0.0 and 1.0."
             );
-            assert!(multiplier > 0.into(), "Multiplier must be greater then 0.");
-            assert!(
-                multiplier > base_multiplier,
-                "Multiplier must be greater then Base Multiplier."
-            );
             assert!(
                 reserve_factor >= 0.into() && reserve_factor <= 1.into(),
                 "Reserve Factor must be between 0.0 and 1.0."
             );
-            assert!(
-                kink >= 0.into() && kink <= 100.into(),
-                "Kink must be between 0 and 100."
-            );
+            let interest_rate_model = InterestRateModel::LinearKink {
+                base,
+                base_multiplier,
+                multiplier,
+                kink,
+            };
+            interest_rate_model.validate();
             if self.ltv_ratios.contains_key(&resource_address) {
                 panic!("Pool already exists for this resource address.");
             }
             self.pools.insert(resource_address, pool_component);
             let now = Runtime::current_epoch().number();
+            // The Pool component does not expose its last update time, so interest accrues
+            // from the insertion and the interest since the last operation of the previous
+            // protocol on the pool is lost. The vault is assumed to hold the deposits and the
+            // reserve not lent out, previous protocol versions had no other loans
             let pool_balances = pool_component.get_pool_balances();
             let data = PoolParameters {
                 balances_updated_at: now,
-                base,
-                base_multiplier,
-                multiplier,
-                kink,
+                interest_rate_model,
                 reserve_factor,
                 ltv_ratio,
                 min_collateral_ratio: Decimal::one(),
@@ -258,14 +340,47 @@ mod lending_protocol {
                 repay_locked: false,
                 pool_reserve: dec!("0.2"),
                 deposit_limit: dec!("100000"),
+                borrow_cap: Decimal::ZERO,
                 deposit_balance: pool_balances.0,
                 sd_balance: pool_balances.1,
                 borrow_balance: pool_balances.2,
                 sb_balance: pool_balances.3,
                 reserve_balance: pool_balances.4,
+                vault_balance: pool_balances.0 + pool_balances.4 - pool_balances.2,
+                last_accrued_at: get_current_time(),
+                borrow_index: Decimal::ONE,
+                liquidity_index: Decimal::ONE,
+                stable_borrow_balance: Decimal::ZERO,
+                average_stable_rate: Decimal::ZERO,
+                stable_borrow_locked: true,
+                stable_rate_premium: dec!("0.02"),
+                stable_rebalance_utilisation: dec!("0.95"),
+                stable_rebalance_min_duration: 60 * 60,
+                stable_rebalance_since: 0,
+                fixed_borrow_balance: Decimal::ZERO,
+                fixed_borrow_locked: true,
+                fixed_loan_rate: dec!("0.08"),
+                fixed_loan_penalty_rate: dec!("0.1"),
+                fixed_loan_max_term: 105120,
+                flash_loan_fee: dec!("0.0009"),
+                flash_loan_balance: Decimal::ZERO,
+                liquidation_auction: false,
+                max_liquidation_bonus: dec!("0.1"),
+                liquidation_auction_duration: 12,
+                bad_debt: Decimal::ZERO,
+                bad_debt_covered: Decimal::ZERO,
+                safety_module_share: Decimal::ZERO,
+                isolated: false,
+                isolation_debt_ceiling: Decimal::ZERO,
+                isolation_debt: IndexMap::new(),
+                borrowable_in_isolation: false,
             };
             self.ltv_ratios.insert(resource_address, ltv_ratio);
             self.pool_parameters.insert(resource_address, data);
+            Runtime::emit_event(PoolCreationEvent {
+                resource_address,
+                pool_component_address: pool_component.address(),
+            });
             self.admin_signature_check = HashMap::new();
         }
 
@@ -288,19 +403,17 @@ mod lending_protocol {
                 ltv_ratio >= 0.into() && ltv_ratio <= 1.into(),
                 "LTV must be between 0.0 and 1.0."
             );
-            assert!(multiplier > 0.into(), "Multiplier must be greater then 0.");
-            assert!(
-                multiplier > base_multiplier,
-                "Multiplier must be greater than Base Multiplier."
-            );
             assert!(
                 reserve_factor >= 0.into() && reserve_factor <= 1.into(),
                 "Reserve Factor must be between 0.0 and 1.0."
             );
-            assert!(
-                kink >= 0.into() && kink <= 100.into(),
-                "Kink must be between 0 and 100."
-            );
+            let interest_rate_model = InterestRateModel::LinearKink {
+                base,
+                base_multiplier,
+                multiplier,
+                kink,
+            };
+            interest_rate_model.validate();
             if self.ltv_ratios.contains_key(&resource_address) {
                 panic!("Pool already exists for this resource address.");
             }
@@ -313,10 +426,7 @@ mod lending_protocol {
 
             let data = PoolParameters {
                 balances_updated_at: now,
-                base,
-                base_multiplier,
-                multiplier,
-                kink,
+                interest_rate_model,
                 reserve_factor,
                 ltv_ratio,
                 min_collateral_ratio: Decimal::one(),
@@ -330,14 +440,47 @@ mod lending_protocol {
                 repay_locked: false,
                 pool_reserve: dec!("0.2"),
                 deposit_limit: dec!("100000"),
+                borrow_cap: Decimal::ZERO,
                 deposit_balance: Decimal::zero(),
                 sd_balance: Decimal::zero(),
                 borrow_balance: Decimal::zero(),
                 sb_balance: Decimal::zero(),
                 reserve_balance: Decimal::zero(),
+                vault_balance: Decimal::zero(),
+                last_accrued_at: get_current_time(),
+                borrow_index: Decimal::ONE,
+                liquidity_index: Decimal::ONE,
+                stable_borrow_balance: Decimal::ZERO,
+                average_stable_rate: Decimal::ZERO,
+                stable_borrow_locked: true,
+                stable_rate_premium: dec!("0.02"),
+                stable_rebalance_utilisation: dec!("0.95"),
+                stable_rebalance_min_duration: 60 * 60,
+                stable_rebalance_since: 0,
+                fixed_borrow_balance: Decimal::ZERO,
+                fixed_borrow_locked: true,
+                fixed_loan_rate: dec!("0.08"),
+                fixed_loan_penalty_rate: dec!("0.1"),
+                fixed_loan_max_term: 105120,
+                flash_loan_fee: dec!("0.0009"),
+                flash_loan_balance: Decimal::ZERO,
+                liquidation_auction: false,
+                max_liquidation_bonus: dec!("0.1"),
+                liquidation_auction_duration: 12,
+                bad_debt: Decimal::ZERO,
+                bad_debt_covered: Decimal::ZERO,
+                safety_module_share: Decimal::ZERO,
+                isolated: false,
+                isolation_debt_ceiling: Decimal::ZERO,
+                isolation_debt: IndexMap::new(),
+                borrowable_in_isolation: false,
             };
 
             self.pool_parameters.insert(resource_address, data);
+            Runtime::emit_event(PoolCreationEvent {
+                resource_address,
+                pool_component_address: pool_component_address.1,
+            });
             self.admin_signature_check = HashMap::new();
             pool_component_address
         }
@@ -345,7 +488,7 @@ mod lending_protocol {
         pub fn create_user_and_deposit(&mut self, asset: Bucket) -> NonFungibleBucket {
             let resource_address = asset.resource_address();
             let asset_amount = asset.amount();
-            let pool_parameters = self.pool_parameters.get(&resource_address).unwrap().clone();
+            let pool_parameters = self.accrue_pool_interest(resource_address);
 
             let deposit_locked = pool_parameters.deposit_locked;
             if deposit_locked {
@@ -353,8 +496,8 @@ mod lending_protocol {
             }
             let pool_deposit_limit = pool_parameters.deposit_limit;
             let mut asset_total_deposit_balance = pool_parameters.deposit_balance;
-            let mut asset_total_borrow_balance = pool_parameters.borrow_balance;
-            let mut asset_total_reserve_balance = pool_parameters.reserve_balance;
+            let asset_total_borrow_balance = pool_parameters.borrow_balance;
+            let asset_total_reserve_balance = pool_parameters.reserve_balance;
             let mut sd_balance = pool_parameters.sd_balance;
 
             if pool_deposit_limit > Decimal::ZERO {
@@ -363,25 +506,8 @@ mod lending_protocol {
                     panic!("Deposit limit is {} .", pool_deposit_limit);
                 }
             }
-            let utilisation =
-                get_utilisation(asset_total_deposit_balance, asset_total_borrow_balance);
-            let borrow_rate = calculate_borrow_rate(
-                pool_parameters.multiplier,
-                pool_parameters.base_multiplier,
-                pool_parameters.base,
-                pool_parameters.kink,
-                utilisation,
-            );
-            let borrow_apr = calculate_borrow_apr(borrow_rate, pool_parameters.balances_updated_at);
-            let interests = calculate_interests(
-                asset_total_borrow_balance,
-                borrow_apr,
-                pool_parameters.reserve_factor,
-            );
-            asset_total_deposit_balance += interests.2;
-
             let sd_interest =
-                calculate_s_interest(asset.amount(), asset_total_deposit_balance, sd_balance);
+                calculate_sd_to_mint(asset.amount(), asset_total_deposit_balance, sd_balance);
             sd_balance += sd_interest;
             let mut user_count = match self.user_resource_manager.total_supply() {
                 Some(value) => value,
@@ -401,12 +527,15 @@ mod lending_protocol {
                 key_image_url: "https://demo.srwa.io/images/badge.png".into(),
                 deposits,
                 borrows,
+                stable_borrows: IndexMap::new(),
+                fixed_loans: IndexMap::new(),
+                liquidation_auction_started_at: 0,
+                e_mode_category: 0,
+                isolation_debt: IndexMap::new(),
                 minted_at: now,
                 updated_at: now,
             };
             let user = self.user_resource_manager.mint_non_fungible(&user_id, data);
-            asset_total_borrow_balance += interests.0;
-            asset_total_reserve_balance += interests.1;
             asset_total_deposit_balance += asset_amount;
             self.update_pool_balances(
                 resource_address,
@@ -416,20 +545,16 @@ mod lending_protocol {
                 pool_parameters.sb_balance,
                 asset_total_reserve_balance,
             );
-            let mut pool = self.pools.get(&resource_address).unwrap().clone();
-            let non_fungible_local_ids: IndexSet<NonFungibleLocalId> =
-                self.protocol_badge.non_fungible_local_ids(1);
-            self.protocol_badge
-                .authorize_with_non_fungibles(&non_fungible_local_ids, || {
-                    pool.put(
-                        asset,
-                        asset_total_deposit_balance,
-                        sd_balance,
-                        asset_total_borrow_balance,
-                        pool_parameters.sb_balance,
-                        asset_total_reserve_balance,
-                    )
-                });
+            self.pool_put(resource_address, asset);
+            let (sd_price, sb_price) = self.get_token_prices(resource_address);
+            Runtime::emit_event(DepositEvent {
+                user_id,
+                resource_address,
+                amount: asset_amount,
+                sd_amount: sd_interest,
+                sd_price,
+                sb_price,
+            });
             user
         }
 
@@ -437,7 +562,7 @@ mod lending_protocol {
             let resource_address = asset.resource_address();
             let asset_amount = asset.amount();
             let user_badge_resource_address = user_badge.resource_address();
-            let pool_parameters = self.pool_parameters.get(&resource_address).unwrap().clone();
+            let pool_parameters = self.accrue_pool_interest(resource_address);
 
             let deposit_locked = pool_parameters.deposit_locked;
             if deposit_locked {
@@ -445,8 +570,8 @@ mod lending_protocol {
             }
             let pool_deposit_limit = pool_parameters.deposit_limit;
             let mut asset_total_deposit_balance = pool_parameters.deposit_balance;
-            let mut asset_total_borrow_balance = pool_parameters.borrow_balance;
-            let mut asset_total_reserve_balance = pool_parameters.reserve_balance;
+            let asset_total_borrow_balance = pool_parameters.borrow_balance;
+            let asset_total_reserve_balance = pool_parameters.reserve_balance;
             let mut sd_balance = pool_parameters.sd_balance;
 
             if pool_deposit_limit > Decimal::ZERO {
@@ -455,32 +580,13 @@ mod lending_protocol {
                     panic!("Deposit limit is {} .", pool_deposit_limit);
                 }
             }
-            let utilisation =
-                get_utilisation(asset_total_deposit_balance, asset_total_borrow_balance);
-            let borrow_rate = calculate_borrow_rate(
-                pool_parameters.multiplier,
-                pool_parameters.base_multiplier,
-                pool_parameters.base,
-                pool_parameters.kink,
-                utilisation,
-            );
-            let borrow_apr = calculate_borrow_apr(borrow_rate, pool_parameters.balances_updated_at);
-            let interests = calculate_interests(
-                asset_total_borrow_balance,
-                borrow_apr,
-                pool_parameters.reserve_factor,
-            );
-            asset_total_deposit_balance += interests.2;
-
             let sd_interest =
-                calculate_s_interest(asset.amount(), asset_total_deposit_balance, sd_balance);
+                calculate_sd_to_mint(asset.amount(), asset_total_deposit_balance, sd_balance);
             sd_balance += sd_interest;
             let manager_address = self.user_resource_manager.address();
             if manager_address != user_badge_resource_address {
                 panic!("User does not exist!");
             };
-            asset_total_borrow_balance += interests.0;
-            asset_total_reserve_balance += interests.1;
             asset_total_deposit_balance += asset_amount;
 
             self.update_pool_balances(
@@ -498,31 +604,63 @@ mod lending_protocol {
             let mut user: UserData = self
                 .user_resource_manager
                 .get_non_fungible_data(&non_fungible_id);
+            let auction_started = user.liquidation_auction_started_at > 0;
+            // Loans taken before the isolated asset is deposited follow isolation mode too
+            let borrowed_assets = user.get_borrowed_assets();
+            let isolation_checked = pool_parameters.isolated && !borrowed_assets.is_empty();
+            if isolation_checked {
+                self.accrue_user_pools(&user);
+                for asset_address in borrowed_assets {
+                    assert!(
+                        self.pool_parameters
+                            .get(&asset_address)
+                            .unwrap()
+                            .borrowable_in_isolation,
+                        "Loans of assets not borrowable in isolation."
+                    );
+                }
+            }
             user.on_deposit(resource_address, sd_interest);
+            self.update_isolation_debt(&mut user);
+            if isolation_checked {
+                let prices = self.get_prices();
+                let isolated_pool_parameters =
+                    self.pool_parameters.get(&resource_address).unwrap();
+                assert!(
+                    isolated_pool_parameters.get_isolation_debt_value(&prices)
+                        <= isolated_pool_parameters.isolation_debt_ceiling,
+                    "Isolation debt ceiling exceeded."
+                );
+            }
             self.user_resource_manager.update_non_fungible_data(
                 &non_fungible_id,
                 "deposits",
                 user.deposits,
             );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "isolation_debt",
+                user.isolation_debt,
+            );
             self.user_resource_manager.update_non_fungible_data(
                 &non_fungible_id,
                 "updated_at",
                 Runtime::current_epoch().number(),
             );
-            let mut pool = self.pools.get(&resource_address).unwrap().clone();
-            let non_fungible_local_ids: IndexSet<NonFungibleLocalId> =
-                self.protocol_badge.non_fungible_local_ids(1);
-            self.protocol_badge
-                .authorize_with_non_fungibles(&non_fungible_local_ids, || {
-                    pool.put(
-                        asset,
-                        asset_total_deposit_balance,
-                        sd_balance,
-                        asset_total_borrow_balance,
-                        pool_parameters.sb_balance,
-                        asset_total_reserve_balance,
-                    )
-                });
+            self.pool_put(resource_address, asset);
+            // Ends the liquidation auction once the position is healthy again
+            if auction_started {
+                self.refresh_liquidation_auction(&non_fungible_id);
+            }
+            let (sd_price, sb_price) = self.get_token_prices(resource_address);
+            Runtime::emit_event(DepositEvent {
+                user_id: non_fungible_id,
+                resource_address,
+                amount: asset_amount,
+                sd_amount: sd_interest,
+                sd_price,
+                sb_price,
+            });
         }
 
         pub fn withdraw(
@@ -537,35 +675,6 @@ mod lending_protocol {
             if manager_address != user_badge_resource_address {
                 panic!("User does not exist!");
             };
-            let pool_parameters = self.pool_parameters.get(&resource_address).unwrap().clone();
-            let withdraw_locked = pool_parameters.withdraw_locked;
-            if withdraw_locked {
-                panic!("Withdrawing is locked for now!");
-            }
-            let mut asset_total_deposit_balance = pool_parameters.deposit_balance;
-            let mut asset_total_borrow_balance = pool_parameters.borrow_balance;
-            let mut asset_total_reserve_balance = pool_parameters.reserve_balance;
-            let mut sd_balance = pool_parameters.sd_balance;
-
-            let available_liquidity = self.available_liquidity(
-                asset_total_deposit_balance,
-                asset_total_borrow_balance,
-                asset_total_reserve_balance,
-                pool_parameters.pool_reserve,
-            );
-            if available_liquidity < amount {
-                panic!("Available liquidity is {}: ", available_liquidity);
-            }
-
-            let asset_ltv_ratio = pool_parameters.ltv_ratio;
-            let mut prices = HashMap::new();
-            for (&res_address, &_ratio) in &self.ltv_ratios {
-                let mut price_in_xrd = Decimal::ONE;
-                if res_address != XRD {
-                    price_in_xrd = self.oracle_address.get_price_in_xrd(res_address);
-                }
-                prices.insert(res_address, price_in_xrd);
-            }
             let non_fungible_id = user_badge
                 .check(manager_address)
                 .as_non_fungible()
@@ -573,59 +682,25 @@ mod lending_protocol {
             let mut user: UserData = self
                 .user_resource_manager
                 .get_non_fungible_data(&non_fungible_id);
-            let user_deposit_balance =
-                user.get_deposit(resource_address) * asset_total_deposit_balance / sd_balance;
-            if user_deposit_balance < amount {
-                panic!(
-                    "User does not have enough deposit balance to withdraw. Max withdrawal is: {}",
-                    user_deposit_balance
-                );
-            }
-            let mut sb_price = Decimal::ONE;
-            if pool_parameters.sb_balance != Decimal::zero() {
-                sb_price = asset_total_borrow_balance / pool_parameters.sb_balance;
-            }
-            let user_borrow = user.get_borrow(resource_address) * sb_price;
-            let max_withdraw =
-                asset_total_deposit_balance - user_borrow / pool_parameters.max_borrow_percent;
-            if amount > max_withdraw {
-                panic!("Max withdraw amount is {}: ", max_withdraw);
+            self.accrue_user_pools(&user);
+            let pool_parameters = self.accrue_pool_interest(resource_address);
+            let withdraw_locked = pool_parameters.withdraw_locked;
+            if withdraw_locked {
+                panic!("Withdrawing is locked for now!");
             }
+            let mut asset_total_deposit_balance = pool_parameters.deposit_balance;
+            let asset_total_borrow_balance = pool_parameters.borrow_balance;
+            let asset_total_reserve_balance = pool_parameters.reserve_balance;
+            let mut sd_balance = pool_parameters.sd_balance;
 
-            let total_collateral_and_loan = user.calculate_total_collateral_and_loan(
-                &self.pool_parameters,
-                &self.ltv_ratios,
-                prices.clone(),
+            let limits = self.get_withdraw_limits(
+                &user,
+                resource_address,
+                &self.get_accrued_pool_parameters(),
             );
-            let user_available_collateral =
-                total_collateral_and_loan.0 - total_collateral_and_loan.1;
-            let withdrawable_amount_in_xrd = user_available_collateral / asset_ltv_ratio;
-            let cost_of_asset_in_terms_of_xrd = prices.get(&resource_address).unwrap();
-            let withdrawable_amount = withdrawable_amount_in_xrd / *cost_of_asset_in_terms_of_xrd;
-
-            if amount > withdrawable_amount {
-                panic!("Max withdrawal amount is {}: ", withdrawable_amount);
-            }
+            MaxAmount::assert_within_limits("withdraw", amount, &limits);
 
-            let utilisation =
-                get_utilisation(asset_total_deposit_balance, asset_total_borrow_balance);
-            let borrow_rate = calculate_borrow_rate(
-                pool_parameters.multiplier,
-                pool_parameters.base_multiplier,
-                pool_parameters.base,
-                pool_parameters.kink,
-                utilisation,
-            );
-            let borrow_apr = calculate_borrow_apr(borrow_rate, pool_parameters.balances_updated_at);
-            let interests = calculate_interests(
-                asset_total_borrow_balance,
-                borrow_apr,
-                pool_parameters.reserve_factor,
-            );
-            asset_total_deposit_balance += interests.2;
-            let sd_interest = calculate_s_interest(amount, asset_total_deposit_balance, sd_balance);
-            asset_total_borrow_balance += interests.0;
-            asset_total_reserve_balance += interests.1;
+            let sd_interest = calculate_sd_to_burn(amount, asset_total_deposit_balance, sd_balance);
             asset_total_deposit_balance -= amount;
             sd_balance -= sd_interest;
             self.update_pool_balances(
@@ -638,31 +713,32 @@ mod lending_protocol {
             );
 
             user.on_withdraw(resource_address, sd_interest);
+            self.update_isolation_debt(&mut user);
             self.user_resource_manager.update_non_fungible_data(
                 &non_fungible_id,
                 "deposits",
                 user.deposits,
             );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "isolation_debt",
+                user.isolation_debt,
+            );
             self.user_resource_manager.update_non_fungible_data(
                 &non_fungible_id,
                 "updated_at",
                 Runtime::current_epoch().number(),
             );
-            let mut pool = self.pools.get(&resource_address).unwrap().clone();
-            let non_fungible_local_ids: IndexSet<NonFungibleLocalId> =
-                self.protocol_badge.non_fungible_local_ids(1);
-            let withdrawn_asset =
-                self.protocol_badge
-                    .authorize_with_non_fungibles(&non_fungible_local_ids, || {
-                        pool.take(
-                            amount,
-                            asset_total_deposit_balance,
-                            sd_balance,
-                            asset_total_borrow_balance,
-                            pool_parameters.sb_balance,
-                            asset_total_reserve_balance,
-                        )
-                    });
+            let withdrawn_asset = self.pool_take(resource_address, amount);
+            let (sd_price, sb_price) = self.get_token_prices(resource_address);
+            Runtime::emit_event(WithdrawEvent {
+                user_id: non_fungible_id,
+                resource_address,
+                amount,
+                sd_amount: sd_interest,
+                sd_price,
+                sb_price,
+            });
             withdrawn_asset
         }
 
@@ -678,33 +754,6 @@ mod lending_protocol {
             if manager_address != user_badge_resource_address {
                 panic!("User does not exist!");
             };
-            let pool_parameters = self.pool_parameters.get(&asset_address).unwrap().clone();
-            let borrow_locked = pool_parameters.borrow_locked;
-            if borrow_locked {
-                panic!("Borrowing is locked for now!");
-            }
-
-            let available_liquidity = self.available_liquidity(
-                pool_parameters.deposit_balance,
-                pool_parameters.borrow_balance,
-                pool_parameters.reserve_balance,
-                pool_parameters.pool_reserve,
-            );
-            if available_liquidity < amount {
-                panic!("Available liquidity amount is {}: ", available_liquidity);
-            }
-
-            let mut prices = HashMap::new();
-            for (&res_address, &_ratio) in &self.ltv_ratios {
-                let mut price_in_xrd = Decimal::ONE;
-                if res_address != XRD {
-                    price_in_xrd = self.oracle_address.get_price_in_xrd(res_address);
-                }
-                prices.insert(res_address, price_in_xrd);
-            }
-            let cost_of_asset_in_terms_of_xrd = prices.get(&asset_address).unwrap();
-
-            let borrow_amount_in_terms_of_xrd = amount * *cost_of_asset_in_terms_of_xrd;
             let non_fungible_id = user_badge
                 .check(manager_address)
                 .as_non_fungible()
@@ -712,56 +761,21 @@ mod lending_protocol {
             let mut user: UserData = self
                 .user_resource_manager
                 .get_non_fungible_data(&non_fungible_id);
-            let total_collateral_and_loan = user.calculate_total_collateral_and_loan(
-                &self.pool_parameters,
-                &self.ltv_ratios,
-                prices.clone(),
-            );
-            let user_available_collateral =
-                total_collateral_and_loan.0 - total_collateral_and_loan.1;
-            assert!(
-                user_available_collateral >= borrow_amount_in_terms_of_xrd,
-                "[borrow_asset][POOL] User does not have enough collateral. Requested loan with \
-                      value of `{:?}` XRD but only has `{:?}` XRD of available collateral.",
-                borrow_amount_in_terms_of_xrd,
-                user_available_collateral
-            );
-            let max_borrow_per_pool =
-                pool_parameters.deposit_balance * pool_parameters.max_borrow_percent;
-            let mut sb_price = Decimal::ONE;
-            if pool_parameters.sb_balance != Decimal::zero() {
-                sb_price = pool_parameters.borrow_balance / pool_parameters.sb_balance;
-            }
-            let user_borrow = user.get_borrow(asset_address) * sb_price;
-            if amount + user_borrow > max_borrow_per_pool {
-                panic!(
-                    "Max borrow amount is {}: ",
-                    max_borrow_per_pool - user_borrow
-                );
+            self.accrue_user_pools(&user);
+            let pool_parameters = self.accrue_pool_interest(asset_address);
+            let borrow_locked = pool_parameters.borrow_locked;
+            if borrow_locked {
+                panic!("Borrowing is locked for now!");
             }
-            let mut asset_total_deposit_balance = pool_parameters.deposit_balance;
+
+            let limits =
+                self.get_borrow_limits(&user, asset_address, &self.get_accrued_pool_parameters());
+            MaxAmount::assert_within_limits("borrow", amount, &limits);
+            let asset_total_deposit_balance = pool_parameters.deposit_balance;
             let mut asset_total_borrow_balance = pool_parameters.borrow_balance;
-            let mut asset_total_reserve_balance = pool_parameters.reserve_balance;
+            let asset_total_reserve_balance = pool_parameters.reserve_balance;
             let mut sb_balance = pool_parameters.sb_balance;
-            let utilisation =
-                get_utilisation(asset_total_deposit_balance, asset_total_borrow_balance);
-            let borrow_rate = calculate_borrow_rate(
-                pool_parameters.multiplier,
-                pool_parameters.base_multiplier,
-                pool_parameters.base,
-                pool_parameters.kink,
-                utilisation,
-            );
-            let borrow_apr = calculate_borrow_apr(borrow_rate, pool_parameters.balances_updated_at);
-            let interests = calculate_interests(
-                asset_total_borrow_balance,
-                borrow_apr,
-                pool_parameters.reserve_factor,
-            );
-            asset_total_borrow_balance += interests.0;
-            let sb_interest = calculate_s_interest(amount, asset_total_borrow_balance, sb_balance);
-            asset_total_reserve_balance += interests.1;
-            asset_total_deposit_balance += interests.2;
+            let sb_interest = calculate_sb_to_mint(amount, asset_total_borrow_balance, sb_balance);
             asset_total_borrow_balance += amount;
             sb_balance += sb_interest;
             self.update_pool_balances(
@@ -774,32 +788,34 @@ mod lending_protocol {
             );
 
             user.on_borrow(asset_address, sb_interest);
-            let mut pool = self.pools.get(&asset_address).unwrap().clone();
+            self.update_isolation_debt(&mut user);
             self.user_resource_manager.update_non_fungible_data(
                 &non_fungible_id,
                 "borrows",
                 user.borrows,
             );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "isolation_debt",
+                user.isolation_debt,
+            );
             self.user_resource_manager.update_non_fungible_data(
                 &non_fungible_id,
                 "updated_at",
                 Runtime::current_epoch().number(),
             );
-            let non_fungible_local_ids: IndexSet<NonFungibleLocalId> =
-                self.protocol_badge.non_fungible_local_ids(1);
-            let borrowed_asset =
-                self.protocol_badge
-                    .authorize_with_non_fungibles(&non_fungible_local_ids, || {
-                        pool.take(
-                            amount,
-                            asset_total_deposit_balance,
-                            pool_parameters.sd_balance,
-                            asset_total_borrow_balance,
-                            sb_balance,
-                            asset_total_reserve_balance,
-                        )
-                    });
-
+            let borrowed_asset = self.pool_take(asset_address, amount);
+            let (sd_price, sb_price) = self.get_token_prices(asset_address);
+            Runtime::emit_event(BorrowEvent {
+                user_id: non_fungible_id,
+                resource_address: asset_address,
+                loan_type: LoanType::Variable,
+                amount,
+                sb_amount: sb_interest,
+                rate: Decimal::ZERO,
+                sd_price,
+                sb_price,
+            });
             borrowed_asset
         }
 
@@ -811,34 +827,16 @@ mod lending_protocol {
                 panic!("User does not exist!");
             };
             let asset_address = repaid.resource_address();
-            let pool_parameters = self.pool_parameters.get(&asset_address).unwrap().clone();
+            let pool_parameters = self.accrue_pool_interest(asset_address);
             let repay_locked = pool_parameters.repay_locked;
             if repay_locked {
                 panic!("Repaying is locked for now!");
             }
 
-            let mut asset_total_deposit_balance = pool_parameters.deposit_balance;
+            let asset_total_deposit_balance = pool_parameters.deposit_balance;
             let mut asset_total_borrow_balance = pool_parameters.borrow_balance;
-            let mut asset_total_reserve_balance = pool_parameters.reserve_balance;
+            let asset_total_reserve_balance = pool_parameters.reserve_balance;
             let mut sb_balance = pool_parameters.sb_balance;
-            let utilisation =
-                get_utilisation(asset_total_deposit_balance, asset_total_borrow_balance);
-            let borrow_rate = calculate_borrow_rate(
-                pool_parameters.multiplier,
-                pool_parameters.base_multiplier,
-                pool_parameters.base,
-                pool_parameters.kink,
-                utilisation,
-            );
-            let borrow_apr = calculate_borrow_apr(borrow_rate, pool_parameters.balances_updated_at);
-
-            let interests = calculate_interests(
-                asset_total_borrow_balance,
-                borrow_apr,
-                pool_parameters.reserve_factor,
-            );
-            let sb_price = calculate_token_price(asset_total_borrow_balance, sb_balance);
-            asset_total_borrow_balance += interests.0;
             let non_fungible_id: NonFungibleLocalId = user_badge
                 .check(manager_address)
                 .as_non_fungible()
@@ -846,8 +844,10 @@ mod lending_protocol {
             let mut user: UserData = self
                 .user_resource_manager
                 .get_non_fungible_data(&non_fungible_id);
+            let auction_started = user.liquidation_auction_started_at > 0;
             let user_borrow = user.get_borrow(asset_address);
-            let max_repay_amount = user_borrow * sb_price;
+            let max_repay_amount =
+                calculate_debt_value(user_borrow, asset_total_borrow_balance, sb_balance);
             let mut repaid_amount = repaid.amount();
             let mut to_return = Decimal::zero();
             if repaid_amount > max_repay_amount {
@@ -855,453 +855,2219 @@ mod lending_protocol {
                 repaid_amount = max_repay_amount;
             }
             let sb_interest =
-                calculate_s_interest(repaid_amount, asset_total_borrow_balance, sb_balance);
-            asset_total_reserve_balance += interests.1;
-            asset_total_deposit_balance += interests.2;
+                calculate_sb_to_burn(repaid_amount, asset_total_borrow_balance, sb_balance)
+                    .min(user_borrow);
 
             sb_balance -= sb_interest;
+            asset_total_borrow_balance -= repaid_amount;
+            self.update_pool_balances(
+                asset_address,
+                asset_total_deposit_balance,
+                pool_parameters.sd_balance,
+                asset_total_borrow_balance,
+                sb_balance,
+                asset_total_reserve_balance,
+            );
 
             user.on_repay(asset_address, sb_interest);
+            self.update_isolation_debt(&mut user);
             self.user_resource_manager.update_non_fungible_data(
                 &non_fungible_id,
                 "borrows",
                 user.borrows,
             );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "isolation_debt",
+                user.isolation_debt,
+            );
             self.user_resource_manager.update_non_fungible_data(
                 &non_fungible_id,
                 "updated_at",
                 Runtime::current_epoch().number(),
             );
-            asset_total_borrow_balance -= repaid_amount;
             let return_bucket =
                 repaid.take_advanced(to_return, WithdrawStrategy::Rounded(RoundingMode::ToZero));
-            self.update_pool_balances(
-                asset_address,
-                asset_total_deposit_balance,
-                pool_parameters.sd_balance,
-                asset_total_borrow_balance,
-                sb_balance,
-                asset_total_reserve_balance,
-            );
-            let mut pool = self.pools.get(&asset_address).unwrap().clone();
-            let non_fungible_local_ids: IndexSet<NonFungibleLocalId> =
-                self.protocol_badge.non_fungible_local_ids(1);
-            self.protocol_badge
-                .authorize_with_non_fungibles(&non_fungible_local_ids, || {
-                    pool.put(
-                        repaid,
-                        asset_total_deposit_balance,
-                        pool_parameters.sd_balance,
-                        asset_total_borrow_balance,
-                        sb_balance,
-                        asset_total_reserve_balance,
-                    )
-                });
+            self.pool_put(asset_address, repaid);
+            // Ends the liquidation auction once the position is healthy again
+            if auction_started {
+                self.refresh_liquidation_auction(&non_fungible_id);
+            }
+            let (sd_price, sb_price) = self.get_token_prices(asset_address);
+            Runtime::emit_event(RepayEvent {
+                user_id: non_fungible_id,
+                resource_address: asset_address,
+                loan_type: LoanType::Variable,
+                amount: repaid_amount,
+                sb_amount: sb_interest,
+                sd_price,
+                sb_price,
+            });
             return_bucket
         }
 
-        pub fn liquidate(
+        pub fn borrow_stable(
             &mut self,
-            user_id: Decimal,
-            repaid: Bucket,
-            deposited_asset: ResourceAddress,
+            asset_address: ResourceAddress,
+            amount: Decimal,
+            user_badge: Proof,
         ) -> Bucket {
-            let repaid_resource_address = repaid.resource_address();
-            let integer_user_id = user_id
-                .to_string()
-                .parse::<u64>()
-                .expect("Invalid decimal value");
-            let non_fungible_id = NonFungibleLocalId::Integer(integer_user_id.into());
-            // Get the user that is targeted for liquidation
+            let user_badge_resource_address = user_badge.resource_address();
+            let manager_address = self.user_resource_manager.address();
+
+            if manager_address != user_badge_resource_address {
+                panic!("User does not exist!");
+            };
+            let non_fungible_id = user_badge
+                .check(manager_address)
+                .as_non_fungible()
+                .non_fungible_local_id();
             let mut user: UserData = self
                 .user_resource_manager
                 .get_non_fungible_data(&non_fungible_id);
-            //TO DO: Calculate balance based on price
-            let liquidated_user_deposit_balance: Decimal = user.get_deposit(deposited_asset);
-            if liquidated_user_deposit_balance == Decimal::ZERO {
-                panic!("User deposit balance of selected token is 0.");
+            self.accrue_user_pools(&user);
+            let pool_parameters = self.accrue_pool_interest(asset_address);
+            if pool_parameters.borrow_locked || pool_parameters.stable_borrow_locked {
+                panic!("Stable rate borrowing is locked for now!");
             }
-            let repaid_pool_parameters = self
-                .pool_parameters
-                .get(&repaid_resource_address)
-                .unwrap()
-                .clone();
 
-            let mut prices = HashMap::new();
-            for (&res_address, &_ratio) in &self.ltv_ratios {
-                let mut price_in_xrd = Decimal::ONE;
-                if res_address != XRD {
-                    price_in_xrd = self.oracle_address.get_price_in_xrd(res_address);
-                }
-                prices.insert(res_address, price_in_xrd);
-            }
-            let loan_limit_used =
-                user.get_loan_limit_used(&self.pool_parameters, &self.ltv_ratios, prices.clone());
+            let limits =
+                self.get_borrow_limits(&user, asset_address, &self.get_accrued_pool_parameters());
+            MaxAmount::assert_within_limits("borrow", amount, &limits);
 
-            if loan_limit_used == Decimal::ZERO {
-                panic!("No borrow from the user");
+            let stable_rate = pool_parameters.get_stable_rate();
+            user.on_stable_borrow(asset_address, amount, stable_rate);
+            self.pool_parameters
+                .get_mut(&asset_address)
+                .unwrap()
+                .on_stable_borrow(amount, stable_rate);
+            self.update_isolation_debt(&mut user);
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "stable_borrows",
+                user.stable_borrows,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "isolation_debt",
+                user.isolation_debt,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "updated_at",
+                Runtime::current_epoch().number(),
+            );
+            let borrowed_asset = self.pool_take(asset_address, amount);
+            let (sd_price, sb_price) = self.get_token_prices(asset_address);
+            Runtime::emit_event(BorrowEvent {
+                user_id: non_fungible_id,
+                resource_address: asset_address,
+                loan_type: LoanType::Stable,
+                amount,
+                sb_amount: Decimal::ZERO,
+                rate: stable_rate,
+                sd_price,
+                sb_price,
+            });
+            borrowed_asset
+        }
+
+        pub fn repay_stable(&mut self, mut repaid: Bucket, user_badge: Proof) -> Bucket {
+            let user_badge_resource_address = user_badge.resource_address();
+            let manager_address = self.user_resource_manager.address();
+
+            if manager_address != user_badge_resource_address {
+                panic!("User does not exist!");
+            };
+            let asset_address = repaid.resource_address();
+            let pool_parameters = self.accrue_pool_interest(asset_address);
+            let repay_locked = pool_parameters.repay_locked;
+            if repay_locked {
+                panic!("Repaying is locked for now!");
             }
-            let lending_parameters = self.pool_parameters.get(&deposited_asset).unwrap().clone();
 
-            let min_collateral_ratio = lending_parameters.min_collateral_ratio;
+            let non_fungible_id: NonFungibleLocalId = user_badge
+                .check(manager_address)
+                .as_non_fungible()
+                .non_fungible_local_id();
+            let mut user: UserData = self
+                .user_resource_manager
+                .get_non_fungible_data(&non_fungible_id);
+            let auction_started = user.liquidation_auction_started_at > 0;
+            let max_repay_amount = user.get_stable_borrow(asset_address);
+            let mut repaid_amount = repaid.amount();
+            let mut to_return = Decimal::zero();
+            if repaid_amount > max_repay_amount {
+                to_return = repaid_amount - max_repay_amount;
+                repaid_amount = max_repay_amount;
+            }
+            let stable_rate = user.get_stable_rate(asset_address);
+            user.on_stable_repay(asset_address, repaid_amount);
+            self.pool_parameters
+                .get_mut(&asset_address)
+                .unwrap()
+                .on_stable_repay(repaid_amount, stable_rate);
+            self.update_isolation_debt(&mut user);
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "stable_borrows",
+                user.stable_borrows,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "isolation_debt",
+                user.isolation_debt,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "updated_at",
+                Runtime::current_epoch().number(),
+            );
+            let return_bucket =
+                repaid.take_advanced(to_return, WithdrawStrategy::Rounded(RoundingMode::ToZero));
+            self.pool_put(asset_address, repaid);
+            // Ends the liquidation auction once the position is healthy again
+            if auction_started {
+                self.refresh_liquidation_auction(&non_fungible_id);
+            }
+            let (sd_price, sb_price) = self.get_token_prices(asset_address);
+            Runtime::emit_event(RepayEvent {
+                user_id: non_fungible_id,
+                resource_address: asset_address,
+                loan_type: LoanType::Stable,
+                amount: repaid_amount,
+                sb_amount: Decimal::ZERO,
+                sd_price,
+                sb_price,
+            });
+            return_bucket
+        }
 
+        /// Re-prices a stable rate loan to the current stable rate while the pool
+        /// utilisation is above the rebalance threshold. Anyone can call it.
+        pub fn rebalance_stable_borrow(
+            &mut self,
+            user_id: Decimal,
+            asset_address: ResourceAddress,
+        ) {
+            let integer_user_id = user_id
+                .to_string()
+                .parse::<u64>()
+                .expect("Invalid decimal value");
+            let non_fungible_id = NonFungibleLocalId::Integer(integer_user_id.into());
+            let mut user: UserData = self
+                .user_resource_manager
+                .get_non_fungible_data(&non_fungible_id);
+            let pool_parameters = self.accrue_pool_interest(asset_address);
+            // Utilisation pushed above the threshold within the transaction does not count
             assert!(
-                loan_limit_used > min_collateral_ratio,
-                "Liquidation not allowed."
+                is_stable_rebalance_allowed(
+                    pool_parameters.stable_rebalance_since,
+                    get_current_time(),
+                    pool_parameters.stable_rebalance_min_duration,
+                ),
+                "Utilisation has not stayed above the rebalance threshold for {} seconds.",
+                pool_parameters.stable_rebalance_min_duration
+            );
+            let stable_borrow = user.get_stable_borrow(asset_address);
+            assert!(
+                stable_borrow > Decimal::ZERO,
+                "User has no stable rate borrow."
+            );
+            let user_stable_rate = user.get_stable_rate(asset_address);
+            let stable_rate = pool_parameters.get_stable_rate();
+            assert!(
+                user_stable_rate < stable_rate,
+                "Stable rate is already at the current rate."
             );
-            let deposit_and_borrow_in_xrd =
-                user.get_deposit_and_borrow_balance_in_xrd(&self.pool_parameters, &prices);
-            let borrow_amount_in_xrd = deposit_and_borrow_in_xrd.1;
-
-            let deposit_amount_in_xrd = deposit_and_borrow_in_xrd.0;
-
-            let asset_max_liquidation_percent = repaid_pool_parameters.max_liquidation_percent;
 
-            let asset_liquidation_bonus = repaid_pool_parameters.liquidation_bonus;
+            user.on_stable_rebalance(asset_address, stable_rate);
+            {
+                let mut pool_parameters = self.pool_parameters.get_mut(&asset_address).unwrap();
+                pool_parameters.on_stable_repay(stable_borrow, user_stable_rate);
+                pool_parameters.on_stable_borrow(stable_borrow, stable_rate);
+            }
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "stable_borrows",
+                user.stable_borrows,
+            );
+            Runtime::emit_event(StableRebalanceEvent {
+                user_id: non_fungible_id,
+                resource_address: asset_address,
+                rate: stable_rate,
+            });
+        }
 
-            let asset_liquidation_reserve_factor =
-                repaid_pool_parameters.liquidation_reserve_factor;
+        /// Borrows at the pool's fixed loan rate until `maturity` (epoch).
+        /// Returns the borrowed asset and the id of the new loan.
+        pub fn borrow_fixed(
+            &mut self,
+            asset_address: ResourceAddress,
+            amount: Decimal,
+            maturity: u64,
+            user_badge: Proof,
+        ) -> (Bucket, u64) {
+            let user_badge_resource_address = user_badge.resource_address();
+            let manager_address = self.user_resource_manager.address();
 
-            let repaid_asset_total_deposit_balance = repaid_pool_parameters.deposit_balance;
-            let repaid_asset_total_borrow_balance = repaid_pool_parameters.borrow_balance;
-            let repaid_asset_total_sb_balance = repaid_pool_parameters.sb_balance;
+            if manager_address != user_badge_resource_address {
+                panic!("User does not exist!");
+            };
+            let non_fungible_id = user_badge
+                .check(manager_address)
+                .as_non_fungible()
+                .non_fungible_local_id();
+            let mut user: UserData = self
+                .user_resource_manager
+                .get_non_fungible_data(&non_fungible_id);
+            self.accrue_user_pools(&user);
+            let pool_parameters = self.accrue_pool_interest(asset_address);
+            if pool_parameters.borrow_locked || pool_parameters.fixed_borrow_locked {
+                panic!("Fixed-term borrowing is locked for now!");
+            }
+            let now = Runtime::current_epoch().number();
+            assert!(maturity > now, "Maturity must be in the future.");
+            assert!(
+                maturity - now <= pool_parameters.fixed_loan_max_term,
+                "Max loan term is {} epochs.",
+                pool_parameters.fixed_loan_max_term
+            );
 
-            let deposit_balance = lending_parameters.deposit_balance;
-            let borrow_balance = lending_parameters.borrow_balance;
-            let reserve_balance = lending_parameters.reserve_balance;
-            let pool_reserve = lending_parameters.pool_reserve;
+            let limits =
+                self.get_borrow_limits(&user, asset_address, &self.get_accrued_pool_parameters());
+            MaxAmount::assert_within_limits("borrow", amount, &limits);
 
-            let available_liquidity = self.available_liquidity(
-                deposit_balance,
-                borrow_balance,
-                reserve_balance,
-                pool_reserve,
+            let loan_id = user.on_fixed_borrow(
+                asset_address,
+                amount,
+                pool_parameters.fixed_loan_rate,
+                pool_parameters.fixed_loan_penalty_rate,
+                maturity,
             );
-
-            let sb_price = calculate_token_price(
-                repaid_asset_total_borrow_balance,
-                repaid_asset_total_sb_balance,
+            self.pool_parameters
+                .get_mut(&asset_address)
+                .unwrap()
+                .on_fixed_borrow(amount);
+            self.update_isolation_debt(&mut user);
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "fixed_loans",
+                user.fixed_loans,
             );
-            let sd_price = calculate_token_price(deposit_balance, lending_parameters.sd_balance);
-            // Do the liquidation calculations and update the liquidated users state
-            let to_return_amounts = user.on_liquidate(
-                repaid.amount(),
-                repaid_resource_address,
-                borrow_amount_in_xrd,
-                deposit_amount_in_xrd,
-                asset_max_liquidation_percent,
-                asset_liquidation_bonus,
-                asset_liquidation_reserve_factor,
-                liquidated_user_deposit_balance,
-                deposited_asset,
-                prices,
-                available_liquidity,
-                sb_price,
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "isolation_debt",
+                user.isolation_debt,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "updated_at",
+                now,
+            );
+            let borrowed_asset = self.pool_take(asset_address, amount);
+            let (sd_price, sb_price) = self.get_token_prices(asset_address);
+            Runtime::emit_event(BorrowEvent {
+                user_id: non_fungible_id,
+                resource_address: asset_address,
+                loan_type: LoanType::Fixed { loan_id },
+                amount,
+                sb_amount: Decimal::ZERO,
+                rate: pool_parameters.fixed_loan_rate,
                 sd_price,
+                sb_price,
+            });
+            (borrowed_asset, loan_id)
+        }
+
+        pub fn repay_fixed(
+            &mut self,
+            mut repaid: Bucket,
+            loan_id: u64,
+            user_badge: Proof,
+        ) -> Bucket {
+            let user_badge_resource_address = user_badge.resource_address();
+            let manager_address = self.user_resource_manager.address();
+
+            if manager_address != user_badge_resource_address {
+                panic!("User does not exist!");
+            };
+            let non_fungible_id: NonFungibleLocalId = user_badge
+                .check(manager_address)
+                .as_non_fungible()
+                .non_fungible_local_id();
+            let mut user: UserData = self
+                .user_resource_manager
+                .get_non_fungible_data(&non_fungible_id);
+            let auction_started = user.liquidation_auction_started_at > 0;
+            let loan = user.get_fixed_loan(loan_id);
+            let asset_address = repaid.resource_address();
+            assert!(
+                loan.resource_address == asset_address,
+                "Repaid asset does not match the loan asset."
             );
+            let pool_parameters = self.accrue_pool_interest(asset_address);
+            if pool_parameters.repay_locked {
+                panic!("Repaying is locked for now!");
+            }
+            if loan.is_overdue() && loan.overdue_since == 0 {
+                user.mark_fixed_loan_overdue(loan_id);
+            }
 
-            let reward = to_return_amounts.0;
-            let platform_bonus = to_return_amounts.1;
-            let decreased_amount = to_return_amounts.2;
+            let max_repay_amount = user.get_fixed_loan(loan_id).get_amount(get_current_time());
+            let mut repaid_amount = repaid.amount();
+            let mut to_return = Decimal::zero();
+            if repaid_amount > max_repay_amount {
+                to_return = repaid_amount - max_repay_amount;
+                repaid_amount = max_repay_amount;
+            }
+            let (interest_repaid, principal_repaid) = user.on_fixed_repay(loan_id, repaid_amount);
+            self.pool_parameters
+                .get_mut(&asset_address)
+                .unwrap()
+                .on_fixed_repay(principal_repaid, interest_repaid);
+            self.update_isolation_debt(&mut user);
             self.user_resource_manager.update_non_fungible_data(
                 &non_fungible_id,
-                "deposits",
-                user.deposits,
+                "fixed_loans",
+                user.fixed_loans,
             );
             self.user_resource_manager.update_non_fungible_data(
                 &non_fungible_id,
-                "borrows",
-                user.borrows,
+                "isolation_debt",
+                user.isolation_debt,
             );
             self.user_resource_manager.update_non_fungible_data(
                 &non_fungible_id,
                 "updated_at",
                 Runtime::current_epoch().number(),
             );
-            let mut pool = self.pools.get(&deposited_asset).unwrap().clone();
-            let non_fungible_local_ids: IndexSet<NonFungibleLocalId> =
-                self.protocol_badge.non_fungible_local_ids(1);
-            let new_total_deposit = deposit_balance - reward - platform_bonus;
-            let new_total_sd_balance =
-                lending_parameters.sd_balance - (reward + platform_bonus) / sd_price;
-            let new_repaid_asset_total_borrow_balance =
-                repaid_asset_total_borrow_balance - decreased_amount;
-            let new_repaid_sb_balance = repaid_asset_total_sb_balance - decreased_amount / sb_price;
-            let to_return_reward =
-                self.protocol_badge
-                    .authorize_with_non_fungibles(&non_fungible_local_ids, || {
-                        pool.take(
-                            reward,
-                            new_total_deposit,
-                            new_total_sd_balance,
-                            borrow_balance,
-                            lending_parameters.sb_balance,
-                            reserve_balance + platform_bonus,
-                        )
-                    });
-            self.update_pool_balances(
-                deposited_asset,
-                new_total_deposit,
-                new_total_sd_balance,
-                borrow_balance,
-                lending_parameters.sb_balance,
-                reserve_balance + platform_bonus,
+            let return_bucket =
+                repaid.take_advanced(to_return, WithdrawStrategy::Rounded(RoundingMode::ToZero));
+            self.pool_put(asset_address, repaid);
+            // Ends the liquidation auction once the position is healthy again
+            if auction_started {
+                self.refresh_liquidation_auction(&non_fungible_id);
+            }
+            let (sd_price, sb_price) = self.get_token_prices(asset_address);
+            Runtime::emit_event(RepayEvent {
+                user_id: non_fungible_id,
+                resource_address: asset_address,
+                loan_type: LoanType::Fixed { loan_id },
+                amount: repaid_amount,
+                sb_amount: Decimal::ZERO,
+                sd_price,
+                sb_price,
+            });
+            return_bucket
+        }
+
+        /// Marks a fixed-term loan past its maturity as overdue. The penalty rate accrues
+        /// from the maturity either way. Anyone can call it.
+        pub fn mark_fixed_loan_overdue(&mut self, user_id: Decimal, loan_id: u64) {
+            let integer_user_id = user_id
+                .to_string()
+                .parse::<u64>()
+                .expect("Invalid decimal value");
+            let non_fungible_id = NonFungibleLocalId::Integer(integer_user_id.into());
+            let mut user: UserData = self
+                .user_resource_manager
+                .get_non_fungible_data(&non_fungible_id);
+            let loan = user.get_fixed_loan(loan_id);
+            user.mark_fixed_loan_overdue(loan_id);
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "fixed_loans",
+                user.fixed_loans,
             );
+            Runtime::emit_event(FixedLoanOverdueEvent {
+                user_id: non_fungible_id,
+                resource_address: loan.resource_address,
+                loan_id,
+            });
+        }
 
-            if repaid.resource_address() == deposited_asset {
-                self.update_pool_balances(
-                    deposited_asset,
-                    new_total_deposit,
-                    new_total_sd_balance,
-                    new_repaid_asset_total_borrow_balance,
-                    new_repaid_sb_balance,
-                    reserve_balance + platform_bonus,
-                );
-                self.protocol_badge
-                    .authorize_with_non_fungibles(&non_fungible_local_ids, || {
-                        pool.put(
-                            repaid,
-                            new_total_deposit,
-                            new_total_sd_balance,
-                            new_repaid_asset_total_borrow_balance,
-                            new_repaid_sb_balance,
-                            reserve_balance + platform_bonus,
-                        )
+        /// Lends idle pool liquidity for the duration of the transaction. The returned
+        /// receipt can not be deposited and is burned by `repay_flash_loan`.
+        pub fn flash_loan(
+            &mut self,
+            resource_address: ResourceAddress,
+            amount: Decimal,
+        ) -> (Bucket, NonFungibleBucket) {
+            assert!(amount > Decimal::ZERO, "Flash loan amount must be positive.");
+            let pool_parameters = self.accrue_pool_interest(resource_address);
+            if pool_parameters.borrow_locked {
+                panic!("Borrowing is locked for now!");
+            }
+            let idle_liquidity = pool_parameters.deposit_balance + pool_parameters.reserve_balance
+                - pool_parameters.get_total_borrow_balance();
+            if amount > idle_liquidity {
+                panic!("Available liquidity amount is {}: ", idle_liquidity);
+            }
+            let fee = calculate_debt_interest(amount, pool_parameters.flash_loan_fee);
+            let receipt =
+                self.flash_loan_receipt_manager
+                    .mint_ruid_non_fungible(FlashLoanReceipt {
+                        resource_address,
+                        amount,
+                        fee,
                     });
-            } else {
-                let mut borrowed_pool = self.pools.get(&repaid_resource_address).unwrap().clone();
+            self.pool_parameters
+                .get_mut(&resource_address)
+                .unwrap()
+                .flash_loan_balance += amount;
+            let loan = self.pool_take(resource_address, amount);
+            (loan, receipt)
+        }
 
-                self.update_pool_balances(
-                    repaid_resource_address,
-                    repaid_asset_total_deposit_balance,
-                    repaid_pool_parameters.sd_balance,
-                    new_repaid_asset_total_borrow_balance,
-                    new_repaid_sb_balance,
-                    repaid_pool_parameters.reserve_balance,
-                );
-                self.protocol_badge
-                    .authorize_with_non_fungibles(&non_fungible_local_ids, || {
-                        borrowed_pool.put(
-                            repaid,
-                            repaid_asset_total_deposit_balance,
-                            repaid_pool_parameters.sd_balance,
-                            new_repaid_asset_total_borrow_balance,
-                            new_repaid_sb_balance,
-                            repaid_pool_parameters.reserve_balance,
-                        )
-                    });
-            }
-            to_return_reward
+        /// Repays a flash loan with principal plus fee and returns the change
+        pub fn repay_flash_loan(
+            &mut self,
+            mut repaid: Bucket,
+            receipt: NonFungibleBucket,
+        ) -> Bucket {
+            assert!(
+                receipt.resource_address() == self.flash_loan_receipt_manager.address(),
+                "Invalid flash loan receipt."
+            );
+            let loan = receipt.non_fungible::<FlashLoanReceipt>().data();
+            assert!(
+                repaid.resource_address() == loan.resource_address,
+                "Repaid asset does not match the loan asset."
+            );
+            let repay_amount = loan.amount + loan.fee;
+            assert!(
+                repaid.amount() >= repay_amount,
+                "Flash loan repayment is {}.",
+                repay_amount
+            );
+            let to_return = repaid.amount() - repay_amount;
+            let return_bucket =
+                repaid.take_advanced(to_return, WithdrawStrategy::Rounded(RoundingMode::ToZero));
+            self.accrue_pool_interest(loan.resource_address);
+            self.pool_parameters
+                .get_mut(&loan.resource_address)
+                .unwrap()
+                .on_flash_loan_repay(loan.amount, loan.fee);
+            self.pool_put(loan.resource_address, repaid);
+            receipt.burn();
+            Runtime::emit_event(FlashLoanEvent {
+                resource_address: loan.resource_address,
+                amount: loan.amount,
+                fee: loan.fee,
+            });
+            return_bucket
         }
 
-        pub fn collect_reserve_balance(
+        /// Anyone can liquidate a position whose loan limit used is above the min collateral
+        /// ratio of the deposited asset pool. The reward, including the liquidation bonus,
+        /// is returned to the caller.
+        pub fn liquidate(
             &mut self,
-            resource_address: ResourceAddress,
-            amount: Decimal,
-            admin_badge: Proof,
+            user_id: Decimal,
+            repaid: Bucket,
+            deposited_asset: ResourceAddress,
         ) -> Bucket {
-            let is_admin_authorized = self.is_authorized(admin_badge);
-            if is_admin_authorized == false {
-                panic!("Not authorized")
+            let repaid_resource_address = repaid.resource_address();
+            let integer_user_id = user_id
+                .to_string()
+                .parse::<u64>()
+                .expect("Invalid decimal value");
+            let non_fungible_id = NonFungibleLocalId::Integer(integer_user_id.into());
+            // Get the user that is targeted for liquidation
+            let mut user: UserData = self
+                .user_resource_manager
+                .get_non_fungible_data(&non_fungible_id);
+            let repaid_amount = repaid.amount();
+            self.accrue_user_pools(&user);
+            self.accrue_pool_interest(repaid_resource_address);
+            self.accrue_pool_interest(deposited_asset);
+            let mut pools = self.get_accrued_pool_parameters();
+            let stable_borrow_before = user.get_stable_borrow(repaid_resource_address);
+            let stable_rate = user.get_stable_rate(repaid_resource_address);
+            // Do the liquidation calculations and update the liquidated users state
+            let (reward, platform_bonus, decreased_amount) = self
+                .try_liquidate(
+                    &mut user,
+                    &pools,
+                    repaid_resource_address,
+                    repaid_amount,
+                    deposited_asset,
+                )
+                .unwrap_or_else(|error| panic!("{}", error.message()));
+            let stable_repaid =
+                stable_borrow_before - user.get_stable_borrow(repaid_resource_address);
+            Self::apply_liquidation(
+                &mut pools,
+                repaid_resource_address,
+                deposited_asset,
+                reward,
+                platform_bonus,
+                decreased_amount - stable_repaid,
+                stable_repaid,
+                stable_rate,
+            );
+            for resource_address in [repaid_resource_address, deposited_asset] {
+                *self.pool_parameters.get_mut(&resource_address).unwrap() =
+                    pools.get(&resource_address).unwrap().clone();
+            }
+            self.update_isolation_debt(&mut user);
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "deposits",
+                user.deposits,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "borrows",
+                user.borrows,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "stable_borrows",
+                user.stable_borrows,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "isolation_debt",
+                user.isolation_debt,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "updated_at",
+                Runtime::current_epoch().number(),
+            );
+            // Repaid tokens go in first, the balances already count them
+            self.pool_put(repaid_resource_address, repaid);
+            let to_return_reward = self.pool_take(deposited_asset, reward);
+            // Ends the liquidation auction if the position is healthy after the liquidation
+            self.refresh_liquidation_auction(&non_fungible_id);
+            Runtime::emit_event(LiquidationEvent {
+                user_id: non_fungible_id,
+                repaid_resource_address,
+                loan_type: if stable_repaid > Decimal::ZERO {
+                    LoanType::Stable
+                } else {
+                    LoanType::Variable
+                },
+                repaid_amount,
+                deposit_resource_address: deposited_asset,
+                reward,
+                platform_bonus,
+                sd_price: self.get_token_prices(deposited_asset).0,
+                sb_price: self.get_token_prices(repaid_resource_address).1,
+            });
+            to_return_reward
+        }
+
+        /// Liquidates a fixed-term loan. Overdue loans can be liquidated in full even if
+        /// the position is collateralised, other loans only once the position is unhealthy
+        /// and up to the max liquidation percent.
+        pub fn liquidate_fixed_loan(
+            &mut self,
+            user_id: Decimal,
+            loan_id: u64,
+            repaid: Bucket,
+            deposited_asset: ResourceAddress,
+        ) -> Bucket {
+            let repaid_resource_address = repaid.resource_address();
+            let integer_user_id = user_id
+                .to_string()
+                .parse::<u64>()
+                .expect("Invalid decimal value");
+            let non_fungible_id = NonFungibleLocalId::Integer(integer_user_id.into());
+            let mut user: UserData = self
+                .user_resource_manager
+                .get_non_fungible_data(&non_fungible_id);
+            let loan = user.get_fixed_loan(loan_id);
+            assert!(
+                loan.resource_address == repaid_resource_address,
+                "Repaid asset does not match the loan asset."
+            );
+            if user.get_deposit(deposited_asset) == Decimal::ZERO {
+                panic!("User deposit balance of selected token is 0.");
+            }
+            self.accrue_user_pools(&user);
+            let repaid_pool_parameters = self.accrue_pool_interest(repaid_resource_address);
+            let lending_parameters = self.accrue_pool_interest(deposited_asset);
+            if loan.is_overdue() && loan.overdue_since == 0 {
+                user.mark_fixed_loan_overdue(loan_id);
+            }
+            let loan_amount = user.get_fixed_loan(loan_id).get_amount(get_current_time());
+
+            let prices = self.get_prices();
+            let e_mode_category = self.get_user_e_mode_category(&user);
+            if !loan.is_overdue() {
+                let loan_limit_used = user.get_loan_limit_used(
+                    &self.get_accrued_pool_parameters(),
+                    &self.ltv_ratios,
+                    e_mode_category.as_ref(),
+                    prices.clone(),
+                );
+                assert!(
+                    is_liquidatable(
+                        loan_limit_used,
+                        self.get_min_collateral_ratio(&user, deposited_asset)
+                    ),
+                    "Liquidation not allowed."
+                );
+                if self.is_liquidation_auction_pending(
+                    &user,
+                    &repaid_pool_parameters,
+                    deposited_asset,
+                ) {
+                    panic!("{}", LiquidationError::AuctionNotStarted.message());
+                }
+                let max_liquidation_amount =
+                    loan_amount * repaid_pool_parameters.max_liquidation_percent;
+                if repaid.amount() > max_liquidation_amount {
+                    panic!("Max liquidation amount is {}", max_liquidation_amount);
+                }
+            } else if repaid.amount() > loan_amount {
+                panic!("Max liquidation amount is {}", loan_amount);
+            }
+
+            let available_liquidity = self.available_liquidity(
+                lending_parameters.deposit_balance,
+                lending_parameters.get_total_borrow_balance(),
+                lending_parameters.reserve_balance,
+                lending_parameters.pool_reserve,
+            );
+            let (reward, platform_bonus, interest_repaid, principal_repaid) = user
+                .on_liquidate_fixed_loan(
+                    loan_id,
+                    repaid.amount(),
+                    repaid_pool_parameters.liquidation_reserve_factor,
+                    deposited_asset,
+                    prices,
+                    available_liquidity,
+                    &repaid_pool_parameters,
+                    &lending_parameters,
+                    e_mode_category.as_ref(),
+                );
+            {
+                let mut pool_parameters = self.pool_parameters.get_mut(&deposited_asset).unwrap();
+                let deposit_balance = pool_parameters.deposit_balance;
+                let sd_balance = pool_parameters.sd_balance;
+                pool_parameters.sd_balance -=
+                    calculate_sd_to_burn(reward + platform_bonus, deposit_balance, sd_balance);
+                pool_parameters.deposit_balance -= reward + platform_bonus;
+                pool_parameters.reserve_balance += platform_bonus;
+            }
+            self.pool_parameters
+                .get_mut(&repaid_resource_address)
+                .unwrap()
+                .on_fixed_repay(principal_repaid, interest_repaid);
+            self.update_isolation_debt(&mut user);
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "deposits",
+                user.deposits,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "fixed_loans",
+                user.fixed_loans,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "isolation_debt",
+                user.isolation_debt,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "updated_at",
+                Runtime::current_epoch().number(),
+            );
+
+            let repaid_amount = repaid.amount();
+            self.pool_put(repaid_resource_address, repaid);
+            let to_return_reward = self.pool_take(deposited_asset, reward);
+            // Ends the liquidation auction if the position is healthy after the liquidation
+            self.refresh_liquidation_auction(&non_fungible_id);
+            Runtime::emit_event(LiquidationEvent {
+                user_id: non_fungible_id,
+                repaid_resource_address,
+                loan_type: LoanType::Fixed { loan_id },
+                repaid_amount,
+                deposit_resource_address: deposited_asset,
+                reward,
+                platform_bonus,
+                sd_price: self.get_token_prices(deposited_asset).0,
+                sb_price: self.get_token_prices(repaid_resource_address).1,
+            });
+            to_return_reward
+        }
+
+        /// Starts the liquidation auction of an unhealthy position, or ends it once the
+        /// position is healthy again. A position is unhealthy when it can be liquidated
+        /// against any of its deposits. Anyone can call it.
+        pub fn update_liquidation_auction(&mut self, user_id: Decimal) {
+            let integer_user_id = user_id
+                .to_string()
+                .parse::<u64>()
+                .expect("Invalid decimal value");
+            let non_fungible_id = NonFungibleLocalId::Integer(integer_user_id.into());
+            if !self.refresh_liquidation_auction(&non_fungible_id) {
+                panic!("Liquidation auction is up to date.");
+            }
+        }
+
+        /// Writes off the debts of a position left without collateral, e.g. after the
+        /// liquidation of an insolvent user. The loss is taken from the pool reserve first,
+        /// the rest is socialised across depositors. Anyone can call it.
+        pub fn resolve_bad_debt(&mut self, user_id: Decimal) {
+            let integer_user_id = user_id
+                .to_string()
+                .parse::<u64>()
+                .expect("Invalid decimal value");
+            let non_fungible_id = NonFungibleLocalId::Integer(integer_user_id.into());
+            let mut user: UserData = self
+                .user_resource_manager
+                .get_non_fungible_data(&non_fungible_id);
+            self.accrue_user_pools(&user);
+            let (borrows, stable_borrows, fixed_loans) = user.on_resolve_bad_debt();
+            // Written off debt no longer counts against debt ceilings
+            self.update_isolation_debt(&mut user);
+
+            let mut losses: IndexMap<ResourceAddress, (Decimal, Decimal)> = IndexMap::new();
+            let mut add_loss = |resource_address: ResourceAddress, loss: (Decimal, Decimal)| {
+                let total = losses
+                    .entry(resource_address)
+                    .or_insert((Decimal::ZERO, Decimal::ZERO));
+                total.0 += loss.0;
+                total.1 += loss.1;
+            };
+            for (resource_address, sb_balance) in borrows {
+                let loss = self
+                    .pool_parameters
+                    .get_mut(&resource_address)
+                    .unwrap()
+                    .on_bad_debt_borrow(sb_balance);
+                add_loss(resource_address, loss);
+            }
+            for (resource_address, stable_borrow) in stable_borrows {
+                let loss = self
+                    .pool_parameters
+                    .get_mut(&resource_address)
+                    .unwrap()
+                    .on_bad_debt_stable_borrow(
+                        stable_borrow.get_amount(get_current_time()),
+                        stable_borrow.rate,
+                    );
+                add_loss(resource_address, loss);
+            }
+            for (_loan_id, loan) in fixed_loans {
+                let loss = self
+                    .pool_parameters
+                    .get_mut(&loan.resource_address)
+                    .unwrap()
+                    .on_bad_debt_fixed_loan(loan.principal);
+                add_loss(loan.resource_address, loss);
+            }
+
+            for (resource_address, (reserve_loss, depositors_loss)) in losses {
+                self.push_pool_balances(resource_address);
+                let pool_parameters = self.pool_parameters.get(&resource_address).unwrap().clone();
+                Runtime::emit_event(BadDebtEvent {
+                    user_id: non_fungible_id.clone(),
+                    resource_address,
+                    reserve_loss,
+                    depositors_loss,
+                    bad_debt: pool_parameters.bad_debt,
+                    sd_price: pool_parameters.get_token_prices().0,
+                });
+            }
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "borrows",
+                user.borrows,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "stable_borrows",
+                user.stable_borrows,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "fixed_loans",
+                user.fixed_loans,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "isolation_debt",
+                user.isolation_debt,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "liquidation_auction_started_at",
+                user.liquidation_auction_started_at,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "updated_at",
+                Runtime::current_epoch().number(),
+            );
+        }
+
+        /// Opts the user into an e-mode category, or out of it with category 0. All loans
+        /// of the user must be in the category and the position must stay within its loan
+        /// limit with the new parameters.
+        pub fn set_e_mode(&mut self, e_mode_category: u8, user_badge: Proof) {
+            let user_badge_resource_address = user_badge.resource_address();
+            let manager_address = self.user_resource_manager.address();
+
+            if manager_address != user_badge_resource_address {
+                panic!("User does not exist!");
+            };
+            let non_fungible_id = user_badge
+                .check(manager_address)
+                .as_non_fungible()
+                .non_fungible_local_id();
+            let mut user: UserData = self
+                .user_resource_manager
+                .get_non_fungible_data(&non_fungible_id);
+            if e_mode_category > 0 {
+                let category = self
+                    .e_mode_categories
+                    .get(&e_mode_category)
+                    .expect("E-mode category does not exist.")
+                    .clone();
+                assert!(
+                    user.get_borrowed_assets()
+                        .iter()
+                        .all(|resource_address| category.contains(*resource_address)),
+                    "User has loans outside the e-mode category."
+                );
+            }
+            self.accrue_user_pools(&user);
+            user.e_mode_category = e_mode_category;
+            let prices = self.get_prices();
+            let e_mode_category_data = self.get_user_e_mode_category(&user);
+            let (collateral, loan) = user.calculate_total_collateral_and_loan(
+                &self.get_accrued_pool_parameters(),
+                &self.ltv_ratios,
+                e_mode_category_data.as_ref(),
+                prices,
+            );
+            assert!(
+                loan <= collateral,
+                "User does not have enough collateral for the e-mode category."
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "e_mode_category",
+                e_mode_category,
+            );
+            self.user_resource_manager.update_non_fungible_data(
+                &non_fungible_id,
+                "updated_at",
+                Runtime::current_epoch().number(),
+            );
+            Runtime::emit_event(EModeEvent {
+                user_id: non_fungible_id,
+                e_mode_category,
+            });
+        }
+
+        pub fn collect_reserve_balance(
+            &mut self,
+            resource_address: ResourceAddress,
+            amount: Decimal,
+            admin_badge: Proof,
+        ) -> Bucket {
+            let is_admin_authorized = self.is_authorized(admin_badge);
+            if is_admin_authorized == false {
+                panic!("Not authorized")
+            }
+            let pool_parameters = self.accrue_pool_interest(resource_address);
+            let mut reserve_balance = pool_parameters.reserve_balance;
+
+            if amount > reserve_balance {
+                panic!("Available reserve balance is: {}", reserve_balance);
+            }
+            reserve_balance -= amount;
+            self.update_pool_balances(
+                resource_address,
+                pool_parameters.deposit_balance,
+                pool_parameters.sd_balance,
+                pool_parameters.borrow_balance,
+                pool_parameters.sb_balance,
+                reserve_balance,
+            );
+            let mut reserve_bucket = self.pool_take(resource_address, amount);
+            Runtime::emit_event(ReserveCollectionEvent {
+                resource_address,
+                amount,
+                reserve_balance,
+            });
+            // Stakers of the asset's safety module earn their share of the collected reserve
+            if let Some(safety_module) = self.safety_modules.get(&resource_address) {
+                let safety_module = *safety_module;
+                let rewards = reserve_bucket.take_advanced(
+                    amount * pool_parameters.safety_module_share,
+                    WithdrawStrategy::Rounded(RoundingMode::ToZero),
+                );
+                let non_fungible_local_ids: IndexSet<NonFungibleLocalId> =
+                    self.protocol_badge.non_fungible_local_ids(1);
+                let unpaid_rewards = self
+                    .protocol_badge
+                    .authorize_with_non_fungibles(&non_fungible_local_ids, || {
+                        safety_module.add_rewards(rewards)
+                    });
+                if let Some(unpaid_rewards) = unpaid_rewards {
+                    reserve_bucket.put(unpaid_rewards);
+                }
+            }
+            self.admin_signature_check = HashMap::new();
+            reserve_bucket
+        }
+
+        pub fn approve_admin_functions(&mut self, admin_badge: Proof) {
+            let manager = ResourceManager::from(admin_badge.resource_address());
+            let admin_id = admin_badge
+                .check(manager.address())
+                .as_non_fungible()
+                .non_fungible_local_id();
+
+            if self.admin_blacklist.contains(&admin_id) {
+                panic!("Not authorized!")
+            }
+            self.admin_signature_check.insert(admin_id.clone(), true);
+            Runtime::emit_event(AdminApprovalEvent { admin_id });
+        }
+
+        pub fn add_to_blacklist(&mut self, admin_id: Decimal, admin_badge: Proof) {
+            let is_admin_authorized = self.is_authorized(admin_badge);
+            if is_admin_authorized == false {
+                panic!("Not authorized")
+            }
+            self.admin_signature_check = HashMap::new();
+            self.admin_signature_check = HashMap::new();
+            let integer_admin_id = admin_id
+                .to_string()
+                .parse::<u64>()
+                .expect("Invalid decimal value");
+            let non_fungible_id = NonFungibleLocalId::Integer(integer_admin_id.into());
+            self.admin_blacklist.insert(non_fungible_id.clone());
+            Runtime::emit_event(AdminBlacklistEvent {
+                admin_id: non_fungible_id,
+                blacklisted: true,
+            });
+        }
+
+        pub fn remove_from_blacklist(&mut self, admin_id: Decimal, admin_badge: Proof) {
+            let is_admin_authorized = self.is_authorized(admin_badge);
+            if is_admin_authorized == false {
+                panic!("Not authorized")
+            }
+            self.admin_signature_check = HashMap::new();
+            let integer_admin_id = admin_id
+                .to_string()
+                .parse::<u64>()
+                .expect("Invalid decimal value");
+            let non_fungible_id = NonFungibleLocalId::Integer(integer_admin_id.into());
+            self.admin_blacklist.remove(&non_fungible_id);
+            Runtime::emit_event(AdminBlacklistEvent {
+                admin_id: non_fungible_id,
+                blacklisted: false,
+            });
+        }
+
+        pub fn take_protocol_badge(&mut self, admin_badge: Proof) -> NonFungibleBucket {
+            let is_admin_authorized = self.is_authorized(admin_badge);
+            if is_admin_authorized == false {
+                panic!("Not authorized")
+            }
+            self.admin_signature_check = HashMap::new();
+            let protocol_badge = self.protocol_badge.take(1);
+            protocol_badge
+        }
+
+        pub fn mint_admin_badge(&mut self, admin_badge: Proof) -> NonFungibleBucket {
+            let is_admin_authorized = self.is_authorized(admin_badge);
+            if is_admin_authorized == false {
+                panic!("Not authorized")
+            }
+            let resource_manager = NonFungibleResourceManager::from(self.admin_badge_address);
+            let admin_badge_id_counter = self.admin_badge_id_counter;
+            let new_id = admin_badge_id_counter + 1;
+            let admin_name = "Admin ".to_string() + &new_id.to_string();
+            let new_admin_badge = resource_manager.mint_non_fungible(
+                &NonFungibleLocalId::integer(new_id),
+                AdminBadge { name: admin_name },
+            );
+            self.admin_badge_id_counter += 1;
+            Runtime::emit_event(AdminBadgeMintEvent {
+                admin_id: NonFungibleLocalId::integer(new_id),
+            });
+            self.admin_signature_check = HashMap::new();
+            new_admin_badge
+        }
+
+        pub fn get_pool_rates(&self, resource_address: ResourceAddress) -> PoolRates {
+            let mut pool_parameters = self
+                .pool_parameters
+                .get(&resource_address)
+                .expect("Pool does not exist for this resource address.")
+                .clone();
+            pool_parameters.accrue_interest();
+            pool_parameters.get_pool_rates()
+        }
+
+        pub fn get_all_pool_rates(&self) -> IndexMap<ResourceAddress, PoolRates> {
+            self.ltv_ratios
+                .keys()
+                .map(|resource_address| (*resource_address, self.get_pool_rates(*resource_address)))
+                .collect()
+        }
+
+        pub fn get_e_mode_category(&self, e_mode_category: u8) -> Option<EModeCategory> {
+            self.e_mode_categories
+                .get(&e_mode_category)
+                .map(|category| category.clone())
+        }
+
+        /// Largest amount of the asset the user can borrow at a variable rate and the check
+        /// it is limited by, with interest accrued until now. Nothing is written.
+        pub fn preview_max_borrow(
+            &self,
+            user_id: Decimal,
+            asset_address: ResourceAddress,
+        ) -> MaxAmount {
+            let integer_user_id = user_id
+                .to_string()
+                .parse::<u64>()
+                .expect("Invalid decimal value");
+            let non_fungible_id = NonFungibleLocalId::Integer(integer_user_id.into());
+            let user: UserData = self
+                .user_resource_manager
+                .get_non_fungible_data(&non_fungible_id);
+            let pools = self.get_accrued_pool_parameters();
+            if pools
+                .get(&asset_address)
+                .expect("Pool does not exist for this resource address.")
+                .borrow_locked
+            {
+                return MaxAmount {
+                    amount: Decimal::ZERO,
+                    constraint: LimitConstraint::PoolLocked,
+                };
+            }
+            let limits = self.get_borrow_limits(&user, asset_address, &pools);
+            MaxAmount::from_limits(&limits)
+        }
+
+        /// Largest amount of the asset the user can withdraw and the check it is limited by,
+        /// with interest accrued until now. Nothing is written.
+        pub fn preview_max_withdraw(
+            &self,
+            user_id: Decimal,
+            resource_address: ResourceAddress,
+        ) -> MaxAmount {
+            let integer_user_id = user_id
+                .to_string()
+                .parse::<u64>()
+                .expect("Invalid decimal value");
+            let non_fungible_id = NonFungibleLocalId::Integer(integer_user_id.into());
+            let user: UserData = self
+                .user_resource_manager
+                .get_non_fungible_data(&non_fungible_id);
+            let pools = self.get_accrued_pool_parameters();
+            if pools
+                .get(&resource_address)
+                .expect("Pool does not exist for this resource address.")
+                .withdraw_locked
+            {
+                return MaxAmount {
+                    amount: Decimal::ZERO,
+                    constraint: LimitConstraint::PoolLocked,
+                };
+            }
+            let limits = self.get_withdraw_limits(&user, resource_address, &pools);
+            MaxAmount::from_limits(&limits)
+        }
+
+        /// Collateral, debt and distance to liquidation of a user's position, with interest
+        /// accrued until now. Nothing is written.
+        pub fn get_user_health(&self, user_id: Decimal) -> UserHealth {
+            let integer_user_id = user_id
+                .to_string()
+                .parse::<u64>()
+                .expect("Invalid decimal value");
+            let non_fungible_id = NonFungibleLocalId::Integer(integer_user_id.into());
+            let user: UserData = self
+                .user_resource_manager
+                .get_non_fungible_data(&non_fungible_id);
+            self.calculate_user_health(&user, &self.get_accrued_pool_parameters())
+        }
+
+        /// Runs the checks and math of `liquidate` with interest accrued until now, without
+        /// moving tokens or writing anything. Returns the reward, the platform bonus and the
+        /// health the position would be left with, or the reason the liquidation would fail.
+        pub fn simulate_liquidation(
+            &self,
+            user_id: Decimal,
+            repaid_resource_address: ResourceAddress,
+            repaid_amount: Decimal,
+            deposited_asset: ResourceAddress,
+        ) -> Result<LiquidationSimulation, LiquidationError> {
+            let integer_user_id = user_id
+                .to_string()
+                .parse::<u64>()
+                .expect("Invalid decimal value");
+            let non_fungible_id = NonFungibleLocalId::Integer(integer_user_id.into());
+            let mut user: UserData = self
+                .user_resource_manager
+                .get_non_fungible_data(&non_fungible_id);
+            let mut pools = self.get_accrued_pool_parameters();
+            let stable_borrow_before = user.get_stable_borrow(repaid_resource_address);
+            let stable_rate = user.get_stable_rate(repaid_resource_address);
+            let (reward, platform_bonus, decreased_amount) = self.try_liquidate(
+                &mut user,
+                &pools,
+                repaid_resource_address,
+                repaid_amount,
+                deposited_asset,
+            )?;
+            let stable_repaid =
+                stable_borrow_before - user.get_stable_borrow(repaid_resource_address);
+            Self::apply_liquidation(
+                &mut pools,
+                repaid_resource_address,
+                deposited_asset,
+                reward,
+                platform_bonus,
+                decreased_amount - stable_repaid,
+                stable_repaid,
+                stable_rate,
+            );
+            Ok(LiquidationSimulation {
+                reward,
+                platform_bonus,
+                health: self.calculate_user_health(&user, &pools),
+            })
+        }
+
+        /// Accrues interest of the pool and pushes the balances to the Pool component,
+        /// so idle pools report current figures. Anyone can call it.
+        pub fn accrue_interest(&mut self, resource_address: ResourceAddress) {
+            self.accrue_pool_interest(resource_address);
+            self.push_pool_balances(resource_address);
+        }
+
+        pub fn accrue_all(&mut self) {
+            let resource_addresses: Vec<ResourceAddress> =
+                self.ltv_ratios.keys().cloned().collect();
+            for resource_address in resource_addresses {
+                self.accrue_interest(resource_address);
+            }
+        }
+
+        /// Difference between the pool vault and the balances: positive for unaccounted
+        /// tokens (e.g. donations), negative for a deficit
+        pub fn reconcile(&mut self, resource_address: ResourceAddress) -> Decimal {
+            let pool_parameters = self.accrue_pool_interest(resource_address);
+            pool_parameters.get_vault_delta()
+        }
+
+        /// Accounts the vault surplus to the reserve or to depositors. Returns the skimmed amount.
+        pub fn skim(
+            &mut self,
+            resource_address: ResourceAddress,
+            to_reserve: bool,
+            admin_badge: Proof,
+        ) -> Decimal {
+            if !self.is_authorized(admin_badge) {
+                panic!("Not authorized")
+            }
+            let surplus = self.reconcile(resource_address);
+            if surplus <= Decimal::ZERO {
+                panic!("There is no surplus to skim, delta is {}", surplus);
+            }
+            {
+                let mut pool_parameters = self.pool_parameters.get_mut(&resource_address).unwrap();
+                if to_reserve {
+                    pool_parameters.reserve_balance += surplus;
+                } else {
+                    pool_parameters.deposit_balance += surplus;
+                }
+            }
+            self.push_pool_balances(resource_address);
+            Runtime::emit_event(SkimEvent {
+                resource_address,
+                amount: surplus,
+                to_reserve,
+            });
+            self.admin_signature_check = HashMap::new();
+            surplus
+        }
+
+        fn is_authorized(&mut self, admin_badge: Proof) -> bool {
+            let manager = ResourceManager::from(admin_badge.resource_address());
+            let non_fungible_id = admin_badge
+                .check(manager.address())
+                .as_non_fungible()
+                .non_fungible_local_id();
+
+            if self.admin_blacklist.contains(&non_fungible_id)
+                || self.admin_signature_check.len() < 3
+            {
+                return false;
+            } else {
+                return true;
+            }
+        }
+
+        pub fn update_pool_parameters(
+            &mut self,
+            resource_address: ResourceAddress,
+            liquidation_reserve_factor: Decimal,
+            liquidation_bonus: Decimal,
+            max_liquidation_percent: Decimal,
+            max_borrow_percent: Decimal,
+            min_collateral_ratio: Decimal,
+            pool_reserve: Decimal,
+            pool_deposit_limit: Decimal,
+            borrow_cap: Decimal,
+            admin_badge: Proof,
+        ) {
+            let is_admin_authorized = self.is_authorized(admin_badge);
+            if is_admin_authorized == false {
+                panic!("Not authorized")
+            }
+            assert!(borrow_cap >= 0.into(), "Borrow cap must not be negative.");
+            self.pool_parameters
+                .get_mut(&resource_address)
+                .unwrap()
+                .update_pool_parameters(PoolRiskParameters {
+                    liquidation_reserve_factor,
+                    liquidation_bonus,
+                    max_liquidation_percent,
+                    max_borrow_percent,
+                    min_collateral_ratio,
+                    pool_reserve,
+                    deposit_limit: pool_deposit_limit,
+                    borrow_cap,
+                });
+            self.emit_pool_parameters_update(resource_address);
+            self.admin_signature_check = HashMap::new();
+        }
+
+        pub fn update_pool_settings(
+            &mut self,
+            resource_address: ResourceAddress,
+            interest_rate_model: InterestRateModel,
+            reserve_factor: Decimal,
+            ltv_ratio: Decimal,
+            admin_badge: Proof,
+        ) {
+            let is_admin_authorized = self.is_authorized(admin_badge);
+            if is_admin_authorized == false {
+                panic!("Not authorized")
+            }
+            assert!(
+                ltv_ratio >= 0.into() && ltv_ratio <= 1.into(),
+                "LTV must be between 0.0 and 1.0."
+            );
+            assert!(
+                reserve_factor >= 0.into() && reserve_factor <= 1.into(),
+                "Reserve Factor must be between 0.0 and 1.0."
+            );
+            interest_rate_model.validate();
+            // Interest up to now is accrued with the previous model
+            self.accrue_pool_interest(resource_address);
+            self.pool_parameters
+                .get_mut(&resource_address)
+                .unwrap()
+                .update_pool_settings(interest_rate_model, reserve_factor, ltv_ratio);
+            self.ltv_ratios.insert(resource_address, ltv_ratio);
+            self.emit_pool_parameters_update(resource_address);
+            self.admin_signature_check = HashMap::new();
+        }
+
+        pub fn update_stable_borrow_settings(
+            &mut self,
+            resource_address: ResourceAddress,
+            stable_borrow_locked: bool,
+            stable_rate_premium: Decimal,
+            admin_badge: Proof,
+        ) {
+            if !self.is_authorized(admin_badge) {
+                panic!("Not authorized")
+            }
+            assert!(
+                stable_rate_premium >= 0.into(),
+                "Stable rate premium must not be negative."
+            );
+            self.pool_parameters
+                .get_mut(&resource_address)
+                .unwrap()
+                .update_stable_borrow_settings(stable_borrow_locked, stable_rate_premium);
+            self.emit_pool_parameters_update(resource_address);
+            self.admin_signature_check = HashMap::new();
+        }
+
+        /// Sets the utilisation above which stable rate loans can be re-priced and how long
+        /// utilisation has to stay above it first.
+        pub fn update_stable_rebalance_settings(
+            &mut self,
+            resource_address: ResourceAddress,
+            stable_rebalance_utilisation: Decimal,
+            stable_rebalance_min_duration: i64,
+            admin_badge: Proof,
+        ) {
+            if !self.is_authorized(admin_badge) {
+                panic!("Not authorized")
+            }
+            assert!(
+                stable_rebalance_utilisation >= 0.into()
+                    && stable_rebalance_utilisation <= 1.into(),
+                "Stable rebalance utilisation must be between 0.0 and 1.0."
+            );
+            assert!(
+                stable_rebalance_min_duration >= 0,
+                "Stable rebalance min duration must not be negative."
+            );
+            self.accrue_pool_interest(resource_address);
+            self.pool_parameters
+                .get_mut(&resource_address)
+                .unwrap()
+                .update_stable_rebalance_settings(
+                    stable_rebalance_utilisation,
+                    stable_rebalance_min_duration,
+                );
+            self.emit_pool_parameters_update(resource_address);
+            self.admin_signature_check = HashMap::new();
+        }
+
+        pub fn update_fixed_loan_settings(
+            &mut self,
+            resource_address: ResourceAddress,
+            fixed_borrow_locked: bool,
+            fixed_loan_rate: Decimal,
+            fixed_loan_penalty_rate: Decimal,
+            fixed_loan_max_term: u64,
+            admin_badge: Proof,
+        ) {
+            if !self.is_authorized(admin_badge) {
+                panic!("Not authorized")
+            }
+            assert!(
+                fixed_loan_rate >= 0.into(),
+                "Fixed loan rate must not be negative."
+            );
+            assert!(
+                fixed_loan_penalty_rate >= 0.into(),
+                "Fixed loan penalty rate must not be negative."
+            );
+            assert!(
+                fixed_loan_max_term > 0,
+                "Fixed loan max term must be greater then 0."
+            );
+            self.pool_parameters
+                .get_mut(&resource_address)
+                .unwrap()
+                .update_fixed_loan_settings(
+                    fixed_borrow_locked,
+                    fixed_loan_rate,
+                    fixed_loan_penalty_rate,
+                    fixed_loan_max_term,
+                );
+            self.emit_pool_parameters_update(resource_address);
+            self.admin_signature_check = HashMap::new();
+        }
+
+        pub fn update_flash_loan_fee(
+            &mut self,
+            resource_address: ResourceAddress,
+            flash_loan_fee: Decimal,
+            admin_badge: Proof,
+        ) {
+            if !self.is_authorized(admin_badge) {
+                panic!("Not authorized")
+            }
+            assert!(
+                flash_loan_fee >= 0.into() && flash_loan_fee <= 1.into(),
+                "Flash loan fee must be between 0.0 and 1.0."
+            );
+            self.pool_parameters
+                .get_mut(&resource_address)
+                .expect("Pool does not exist for this resource address.")
+                .flash_loan_fee = flash_loan_fee;
+            self.emit_pool_parameters_update(resource_address);
+            self.admin_signature_check = HashMap::new();
+        }
+
+        pub fn update_liquidation_auction_settings(
+            &mut self,
+            resource_address: ResourceAddress,
+            liquidation_auction: bool,
+            max_liquidation_bonus: Decimal,
+            liquidation_auction_duration: u64,
+            admin_badge: Proof,
+        ) {
+            if !self.is_authorized(admin_badge) {
+                panic!("Not authorized")
+            }
+            assert!(
+                max_liquidation_bonus >= 0.into(),
+                "Max liquidation bonus must not be negative."
+            );
+            assert!(
+                liquidation_auction_duration > 0,
+                "Liquidation auction duration must be greater then 0."
+            );
+            self.pool_parameters
+                .get_mut(&resource_address)
+                .expect("Pool does not exist for this resource address.")
+                .update_liquidation_auction_settings(
+                    liquidation_auction,
+                    max_liquidation_bonus,
+                    liquidation_auction_duration,
+                );
+            self.emit_pool_parameters_update(resource_address);
+            self.admin_signature_check = HashMap::new();
+        }
+
+        /// Isolates a pool asset: users with it as collateral can only borrow assets
+        /// borrowable in isolation, up to the debt ceiling in XRD across all users.
+        pub fn update_isolation_settings(
+            &mut self,
+            resource_address: ResourceAddress,
+            isolated: bool,
+            isolation_debt_ceiling: Decimal,
+            borrowable_in_isolation: bool,
+            admin_badge: Proof,
+        ) {
+            if !self.is_authorized(admin_badge) {
+                panic!("Not authorized")
+            }
+            assert!(
+                isolation_debt_ceiling >= 0.into(),
+                "Isolation debt ceiling must not be negative."
+            );
+            self.pool_parameters
+                .get_mut(&resource_address)
+                .expect("Pool does not exist for this resource address.")
+                .update_isolation_settings(
+                    isolated,
+                    isolation_debt_ceiling,
+                    borrowable_in_isolation,
+                );
+            self.emit_pool_parameters_update(resource_address);
+            self.admin_signature_check = HashMap::new();
+        }
+
+        /// Creates or updates an e-mode category. Users already in the category get the
+        /// new parameters right away.
+        pub fn update_e_mode_category(
+            &mut self,
+            e_mode_category: u8,
+            category: EModeCategory,
+            admin_badge: Proof,
+        ) {
+            if !self.is_authorized(admin_badge) {
+                panic!("Not authorized")
+            }
+            assert!(e_mode_category > 0, "E-mode category must be greater then 0.");
+            category.validate();
+            for resource_address in category.assets.iter() {
+                assert!(
+                    self.ltv_ratios.contains_key(resource_address),
+                    "Pool does not exist for this resource address."
+                );
+            }
+            self.e_mode_categories.insert(e_mode_category, category.clone());
+            Runtime::emit_event(EModeCategoryUpdateEvent {
+                e_mode_category,
+                category,
+            });
+            self.admin_signature_check = HashMap::new();
+        }
+
+        /// Creates the safety module of a pool asset. Stakers of the asset receive the
+        /// safety module share of every reserve collection and can be slashed to cover
+        /// bad debt of the pool.
+        pub fn create_safety_module(
+            &mut self,
+            resource_address: ResourceAddress,
+            safety_module_share: Decimal,
+            max_slash_percent: Decimal,
+            cooldown_epochs: u64,
+            admin_badge: Proof,
+        ) {
+            if !self.is_authorized(admin_badge) {
+                panic!("Not authorized")
+            }
+            if self.safety_modules.get(&resource_address).is_some() {
+                panic!("Safety module already exists for this resource address.");
+            }
+            Self::validate_safety_module_settings(
+                safety_module_share,
+                max_slash_percent,
+                cooldown_epochs,
+            );
+            let safety_module = Blueprint::<SafetyModule>::instantiate(
+                self.protocol_rule.clone(),
+                resource_address,
+                max_slash_percent,
+                cooldown_epochs,
+            );
+            self.pool_parameters
+                .get_mut(&resource_address)
+                .expect("Pool does not exist for this resource address.")
+                .safety_module_share = safety_module_share;
+            self.safety_modules.insert(resource_address, safety_module);
+            Runtime::emit_event(SafetyModuleCreationEvent {
+                resource_address,
+                safety_module_component_address: safety_module.address(),
+            });
+            self.emit_pool_parameters_update(resource_address);
+            self.admin_signature_check = HashMap::new();
+        }
+
+        pub fn update_safety_module_settings(
+            &mut self,
+            resource_address: ResourceAddress,
+            safety_module_share: Decimal,
+            max_slash_percent: Decimal,
+            cooldown_epochs: u64,
+            admin_badge: Proof,
+        ) {
+            if !self.is_authorized(admin_badge) {
+                panic!("Not authorized")
+            }
+            Self::validate_safety_module_settings(
+                safety_module_share,
+                max_slash_percent,
+                cooldown_epochs,
+            );
+            let safety_module = *self
+                .safety_modules
+                .get(&resource_address)
+                .expect("Safety module does not exist for this resource address.");
+            let non_fungible_local_ids: IndexSet<NonFungibleLocalId> =
+                self.protocol_badge.non_fungible_local_ids(1);
+            self.protocol_badge
+                .authorize_with_non_fungibles(&non_fungible_local_ids, || {
+                    safety_module.update_settings(max_slash_percent, cooldown_epochs)
+                });
+            self.pool_parameters
+                .get_mut(&resource_address)
+                .unwrap()
+                .safety_module_share = safety_module_share;
+            self.emit_pool_parameters_update(resource_address);
+            self.admin_signature_check = HashMap::new();
+        }
+
+        /// Slashes the safety module of the asset to make up for bad debt the pool has not
+        /// recovered yet. Slashed tokens are returned to depositors.
+        pub fn cover_bad_debt(
+            &mut self,
+            resource_address: ResourceAddress,
+            amount: Decimal,
+            admin_badge: Proof,
+        ) {
+            if !self.is_authorized(admin_badge) {
+                panic!("Not authorized")
+            }
+            let pool_parameters = self.accrue_pool_interest(resource_address);
+            let uncovered_bad_debt = calculate_uncovered_bad_debt(
+                pool_parameters.bad_debt,
+                pool_parameters.bad_debt_covered,
+            );
+            if amount > uncovered_bad_debt {
+                panic!("Uncovered bad debt is {}", uncovered_bad_debt);
+            }
+            let safety_module = *self
+                .safety_modules
+                .get(&resource_address)
+                .expect("Safety module does not exist for this resource address.");
+            let non_fungible_local_ids: IndexSet<NonFungibleLocalId> =
+                self.protocol_badge.non_fungible_local_ids(1);
+            let slashed = self
+                .protocol_badge
+                .authorize_with_non_fungibles(&non_fungible_local_ids, || {
+                    safety_module.slash(amount)
+                });
+            let slashed_amount = slashed.amount();
+            let bad_debt_covered = {
+                let mut pool_parameters = self.pool_parameters.get_mut(&resource_address).unwrap();
+                pool_parameters.deposit_balance += slashed_amount;
+                pool_parameters.bad_debt_covered += slashed_amount;
+                pool_parameters.bad_debt_covered
+            };
+            self.pool_put(resource_address, slashed);
+            Runtime::emit_event(BadDebtCoverEvent {
+                resource_address,
+                amount: slashed_amount,
+                bad_debt_covered,
+                sd_price: self.get_token_prices(resource_address).0,
+            });
+            self.admin_signature_check = HashMap::new();
+        }
+
+        fn validate_safety_module_settings(
+            safety_module_share: Decimal,
+            max_slash_percent: Decimal,
+            cooldown_epochs: u64,
+        ) {
+            assert!(
+                safety_module_share >= 0.into() && safety_module_share <= 1.into(),
+                "Safety module share must be between 0.0 and 1.0."
+            );
+            assert!(
+                max_slash_percent >= 0.into() && max_slash_percent < 1.into(),
+                "Max slash percent must be at least 0.0 and below 1.0."
+            );
+            assert!(
+                cooldown_epochs > 0,
+                "Cooldown epochs must be greater then 0."
+            );
+        }
+
+        fn accrue_pool_interest(&mut self, resource_address: ResourceAddress) -> PoolParameters {
+            let mut pool_parameters = self
+                .pool_parameters
+                .get_mut(&resource_address)
+                .expect("Pool does not exist for this resource address.");
+            let last_accrued_at = pool_parameters.last_accrued_at;
+            pool_parameters.accrue_interest();
+            if pool_parameters.last_accrued_at > last_accrued_at {
+                let (sd_price, sb_price) = pool_parameters.get_token_prices();
+                Runtime::emit_event(InterestAccrualEvent {
+                    resource_address,
+                    borrow_index: pool_parameters.borrow_index,
+                    liquidity_index: pool_parameters.liquidity_index,
+                    reserve_balance: pool_parameters.reserve_balance,
+                    sd_price,
+                    sb_price,
+                });
+            }
+            pool_parameters.clone()
+        }
+
+        // Starts the liquidation auction of an unhealthy position and ends it once the position
+        // is healthy again, from the stored user data. Returns false if it is up to date.
+        fn refresh_liquidation_auction(&mut self, non_fungible_id: &NonFungibleLocalId) -> bool {
+            let user: UserData = self
+                .user_resource_manager
+                .get_non_fungible_data(non_fungible_id);
+            self.accrue_user_pools(&user);
+            let prices = self.get_prices();
+            let e_mode_category = self.get_user_e_mode_category(&user);
+            let loan_limit_used = user.get_loan_limit_used(
+                &self.get_accrued_pool_parameters(),
+                &self.ltv_ratios,
+                e_mode_category.as_ref(),
+                prices,
+            );
+            let unhealthy = !user.deposits.is_empty()
+                && is_liquidatable(loan_limit_used, self.get_liquidation_threshold(&user));
+            let started_at = if !unhealthy {
+                0
+            } else if user.liquidation_auction_started_at == 0 {
+                Runtime::current_epoch().number()
+            } else {
+                user.liquidation_auction_started_at
+            };
+            if started_at == user.liquidation_auction_started_at {
+                return false;
+            }
+            self.user_resource_manager.update_non_fungible_data(
+                non_fungible_id,
+                "liquidation_auction_started_at",
+                started_at,
+            );
+            Runtime::emit_event(LiquidationAuctionEvent {
+                user_id: non_fungible_id.clone(),
+                started_at,
+            });
+            true
+        }
+
+        // Liquidations paying the auction bonus have to wait for the auction to start,
+        // starting it in the same call would pay a bonus of 0
+        fn is_liquidation_auction_pending(
+            &self,
+            user: &UserData,
+            repaid_pool_parameters: &PoolParameters,
+            deposited_asset: ResourceAddress,
+        ) -> bool {
+            if user.liquidation_auction_started_at > 0
+                || !repaid_pool_parameters.liquidation_auction
+            {
+                return false;
+            }
+            // Collateral of the user's e-mode category is liquidated with the category bonus
+            !self
+                .get_user_e_mode_category(user)
+                .is_some_and(|category| category.contains(deposited_asset))
+        }
+
+        // Checks and math of a liquidation, shared by `liquidate` and `simulate_liquidation`.
+        // Updates the passed user data as the liquidation would, from pools with interest
+        // accrued until now. Nothing is written and no tokens move.
+        fn try_liquidate(
+            &self,
+            user: &mut UserData,
+            pools: &IndexMap<ResourceAddress, PoolParameters>,
+            repaid_resource_address: ResourceAddress,
+            repaid_amount: Decimal,
+            deposited_asset: ResourceAddress,
+        ) -> Result<(Decimal, Decimal, Decimal), LiquidationError> {
+            //TO DO: Calculate balance based on price
+            let liquidated_user_deposit_balance: Decimal = user.get_deposit(deposited_asset);
+            if liquidated_user_deposit_balance == Decimal::ZERO {
+                return Err(LiquidationError::NoDeposit);
+            }
+            let repaid_pool_parameters = pools
+                .get(&repaid_resource_address)
+                .expect("Pool does not exist for this resource address.");
+
+            let prices = self.get_prices();
+            let e_mode_category = self.get_user_e_mode_category(user);
+            let loan_limit_used = user.get_loan_limit_used(
+                pools,
+                &self.ltv_ratios,
+                e_mode_category.as_ref(),
+                prices.clone(),
+            );
+
+            if loan_limit_used == Decimal::ZERO {
+                return Err(LiquidationError::NoBorrow);
+            }
+            if !is_liquidatable(
+                loan_limit_used,
+                self.get_min_collateral_ratio(user, deposited_asset),
+            ) {
+                return Err(LiquidationError::PositionHealthy);
             }
-            let pool_parameters = self.pool_parameters.get(&resource_address).unwrap().clone();
-            let mut reserve_balance = pool_parameters.reserve_balance;
+            if self.is_liquidation_auction_pending(user, repaid_pool_parameters, deposited_asset) {
+                return Err(LiquidationError::AuctionNotStarted);
+            }
+            user.try_on_liquidate(
+                repaid_amount,
+                repaid_resource_address,
+                deposited_asset,
+                pools,
+                prices,
+                e_mode_category.as_ref(),
+            )
+        }
 
-            if amount > reserve_balance {
-                panic!("Available reserve balance is: {}", reserve_balance);
+        // Pool balances after a liquidation, shared by `liquidate` and `simulate_liquidation`.
+        // Stable rate debt is repaid before variable rate debt.
+        fn apply_liquidation(
+            pools: &mut IndexMap<ResourceAddress, PoolParameters>,
+            repaid_resource_address: ResourceAddress,
+            deposited_asset: ResourceAddress,
+            reward: Decimal,
+            platform_bonus: Decimal,
+            variable_repaid: Decimal,
+            stable_repaid: Decimal,
+            stable_rate: Decimal,
+        ) {
+            let repaid_pool_parameters = pools.get_mut(&repaid_resource_address).unwrap();
+            if stable_repaid > Decimal::ZERO {
+                repaid_pool_parameters.on_stable_repay(stable_repaid, stable_rate);
             }
-            reserve_balance -= amount;
-            let mut pool = self.pools.get(&resource_address).unwrap().clone();
-            let non_fungible_local_ids: IndexSet<NonFungibleLocalId> =
-                self.protocol_badge.non_fungible_local_ids(1);
-            let reserve_bucket =
-                self.protocol_badge
-                    .authorize_with_non_fungibles(&non_fungible_local_ids, || {
-                        pool.take(
-                            amount,
-                            pool_parameters.deposit_balance,
-                            pool_parameters.sd_balance,
-                            pool_parameters.borrow_balance,
-                            pool_parameters.sb_balance,
-                            reserve_balance,
-                        )
-                    });
-            self.update_pool_balances(
-                resource_address,
-                pool_parameters.deposit_balance,
-                pool_parameters.sd_balance,
-                pool_parameters.borrow_balance,
-                pool_parameters.sb_balance,
-                reserve_balance,
+            let sb_burned = calculate_sb_to_burn(
+                variable_repaid,
+                repaid_pool_parameters.borrow_balance,
+                repaid_pool_parameters.sb_balance,
+            );
+            repaid_pool_parameters.update_balances(
+                repaid_pool_parameters.deposit_balance,
+                repaid_pool_parameters.sd_balance,
+                repaid_pool_parameters.borrow_balance - variable_repaid,
+                repaid_pool_parameters.sb_balance - sb_burned,
+                repaid_pool_parameters.reserve_balance,
+            );
+            let lending_parameters = pools.get_mut(&deposited_asset).unwrap();
+            let sd_burned = calculate_sd_to_burn(
+                reward + platform_bonus,
+                lending_parameters.deposit_balance,
+                lending_parameters.sd_balance,
+            );
+            lending_parameters.update_balances(
+                lending_parameters.deposit_balance - reward - platform_bonus,
+                lending_parameters.sd_balance - sd_burned,
+                lending_parameters.borrow_balance,
+                lending_parameters.sb_balance,
+                lending_parameters.reserve_balance + platform_bonus,
             );
-            self.admin_signature_check = HashMap::new();
-            reserve_bucket
         }
 
-        pub fn approve_admin_functions(&mut self, admin_badge: Proof) {
-            let manager = ResourceManager::from(admin_badge.resource_address());
-            let admin_id = admin_badge
-                .check(manager.address())
-                .as_non_fungible()
-                .non_fungible_local_id();
+        // Health of the position at the current pool balances, without accruing interest
+        fn calculate_user_health(
+            &self,
+            user: &UserData,
+            pool_parameters: &IndexMap<ResourceAddress, PoolParameters>,
+        ) -> UserHealth {
+            let prices = self.get_prices();
+            let e_mode_category = self.get_user_e_mode_category(user);
+            let (collateral_value, debt_value) = user.calculate_total_collateral_and_loan(
+                pool_parameters,
+                &self.ltv_ratios,
+                e_mode_category.as_ref(),
+                prices,
+            );
+            let loan_limit_used = calculate_loan_limit_used(collateral_value, debt_value);
+            let liquidation_threshold = self.get_liquidation_threshold(user);
+            let health_factor = calculate_health_factor(loan_limit_used, liquidation_threshold);
+            UserHealth {
+                collateral_value,
+                debt_value,
+                loan_limit_used,
+                liquidation_threshold,
+                health_factor,
+                liquidation_price_drop: calculate_liquidation_price_drop(health_factor),
+            }
+        }
 
-            if self.admin_blacklist.contains(&admin_id) {
-                panic!("Not authorized!")
+        // Lowest min collateral ratio of the deposited assets, the position can be liquidated
+        // against one of them as soon as its loan limit used is above it
+        fn get_liquidation_threshold(&self, user: &UserData) -> Decimal {
+            user.deposits
+                .keys()
+                .map(|resource_address| self.get_min_collateral_ratio(user, *resource_address))
+                .min()
+                .unwrap_or(Decimal::ZERO)
+        }
+
+        fn get_user_e_mode_category(&self, user: &UserData) -> Option<EModeCategory> {
+            if user.e_mode_category == 0 {
+                return None;
             }
-            self.admin_signature_check.insert(admin_id, true);
+            self.e_mode_categories
+                .get(&user.e_mode_category)
+                .map(|category| category.clone())
         }
 
-        pub fn add_to_blacklist(&mut self, admin_id: Decimal, admin_badge: Proof) {
-            let is_admin_authorized = self.is_authorized(admin_badge);
-            if is_admin_authorized == false {
-                panic!("Not authorized")
+        // Min collateral ratio of the asset, replaced by the liquidation threshold of the
+        // user's e-mode category for assets of the category
+        fn get_min_collateral_ratio(
+            &self,
+            user: &UserData,
+            resource_address: ResourceAddress,
+        ) -> Decimal {
+            match self.get_user_e_mode_category(user) {
+                Some(category) if category.contains(resource_address) => {
+                    category.liquidation_threshold
+                }
+                _ => {
+                    self.pool_parameters
+                        .get(&resource_address)
+                        .unwrap()
+                        .min_collateral_ratio
+                }
             }
-            self.admin_signature_check = HashMap::new();
-            self.admin_signature_check = HashMap::new();
-            let integer_admin_id = admin_id
-                .to_string()
-                .parse::<u64>()
-                .expect("Invalid decimal value");
-            let non_fungible_id = NonFungibleLocalId::Integer(integer_admin_id.into());
-            self.admin_blacklist.insert(non_fungible_id);
         }
 
-        pub fn remove_from_blacklist(&mut self, admin_id: Decimal, admin_badge: Proof) {
-            let is_admin_authorized = self.is_authorized(admin_badge);
-            if is_admin_authorized == false {
-                panic!("Not authorized")
+        // LTV ratio of the asset, replaced by the LTV of the user's e-mode category for
+        // assets of the category
+        fn get_ltv_ratio(&self, user: &UserData, resource_address: ResourceAddress) -> Decimal {
+            match self.get_user_e_mode_category(user) {
+                Some(category) if category.contains(resource_address) => category.ltv_ratio,
+                _ => *self.ltv_ratios.get(&resource_address).unwrap(),
             }
-            self.admin_signature_check = HashMap::new();
-            let integer_admin_id = admin_id
-                .to_string()
-                .parse::<u64>()
-                .expect("Invalid decimal value");
-            let non_fungible_id = NonFungibleLocalId::Integer(integer_admin_id.into());
-            self.admin_blacklist.remove(&non_fungible_id);
         }
 
-        pub fn take_protocol_badge(&mut self, admin_badge: Proof) -> NonFungibleBucket {
-            let is_admin_authorized = self.is_authorized(admin_badge);
-            if is_admin_authorized == false {
-                panic!("Not authorized")
+        // Checks of `borrow`, `borrow_stable` and `borrow_fixed`, as max amounts of the asset
+        fn get_borrow_limits(
+            &self,
+            user: &UserData,
+            asset_address: ResourceAddress,
+            pools: &IndexMap<ResourceAddress, PoolParameters>,
+        ) -> Vec<(LimitConstraint, Decimal)> {
+            let pool_parameters = pools.get(&asset_address).unwrap();
+            let available_liquidity = self.available_liquidity(
+                pool_parameters.deposit_balance,
+                pool_parameters.get_total_borrow_balance(),
+                pool_parameters.reserve_balance,
+                pool_parameters.pool_reserve,
+            );
+            let prices = self.get_prices();
+            let cost_of_asset_in_terms_of_xrd = *prices.get(&asset_address).unwrap();
+            let e_mode_category = self.get_user_e_mode_category(user);
+            let (collateral, loan) = user.calculate_total_collateral_and_loan(
+                pools,
+                &self.ltv_ratios,
+                e_mode_category.as_ref(),
+                prices.clone(),
+            );
+            let user_borrow = calculate_debt_value(
+                user.get_borrow(asset_address),
+                pool_parameters.borrow_balance,
+                pool_parameters.sb_balance,
+            ) + user.get_stable_borrow(asset_address)
+                + user.get_fixed_principal(asset_address);
+            let mut limits = vec![
+                (LimitConstraint::AvailableLiquidity, available_liquidity),
+                (
+                    LimitConstraint::CollateralHeadroom,
+                    calculate_borrow_headroom(collateral, loan, cost_of_asset_in_terms_of_xrd),
+                ),
+                (
+                    LimitConstraint::MaxBorrowPercent,
+                    calculate_max_borrow_percent_headroom(
+                        pool_parameters.deposit_balance,
+                        pool_parameters.max_borrow_percent,
+                        user_borrow,
+                    ),
+                ),
+            ];
+            if let Some(remaining_borrow_cap) = pool_parameters.get_remaining_borrow_cap() {
+                limits.push((LimitConstraint::BorrowCap, remaining_borrow_cap));
             }
-            self.admin_signature_check = HashMap::new();
-            let protocol_badge = self.protocol_badge.take(1);
-            protocol_badge
+            // Users in e-mode can only borrow assets of their category
+            if let Some(category) = e_mode_category {
+                if !category.contains(asset_address) {
+                    limits.push((LimitConstraint::EModeCategory, Decimal::ZERO));
+                }
+            }
+            limits.extend(self.get_isolation_limits(
+                user,
+                pool_parameters,
+                &prices,
+                cost_of_asset_in_terms_of_xrd,
+            ));
+            limits
         }
 
-        pub fn mint_admin_badge(&mut self, admin_badge: Proof) -> NonFungibleBucket {
-            let is_admin_authorized = self.is_authorized(admin_badge);
-            if is_admin_authorized == false {
-                panic!("Not authorized")
+        // Checks of isolated collateral, as max amounts of the asset
+        fn get_isolation_limits(
+            &self,
+            user: &UserData,
+            pool_parameters: &PoolParameters,
+            prices: &HashMap<ResourceAddress, Decimal>,
+            cost_of_asset_in_terms_of_xrd: Decimal,
+        ) -> Vec<(LimitConstraint, Decimal)> {
+            let isolated_asset_addresses = self.get_isolated_collateral(user);
+            let mut limits = vec![];
+            if !isolated_asset_addresses.is_empty() && !pool_parameters.borrowable_in_isolation {
+                limits.push((LimitConstraint::IsolatedCollateral, Decimal::ZERO));
             }
-            let resource_manager = NonFungibleResourceManager::from(self.admin_badge_address);
-            let admin_badge_id_counter = self.admin_badge_id_counter;
-            let new_id = admin_badge_id_counter + 1;
-            let admin_name = "Admin ".to_string() + &new_id.to_string();
-            let new_admin_badge = resource_manager.mint_non_fungible(
-                &NonFungibleLocalId::integer(new_id),
-                AdminBadge { name: admin_name },
-            );
-            self.admin_badge_id_counter += 1;
-            self.admin_signature_check = HashMap::new();
-            new_admin_badge
+            for isolated_asset_address in isolated_asset_addresses {
+                let isolated_pool_parameters = self
+                    .pool_parameters
+                    .get(&isolated_asset_address)
+                    .unwrap()
+                    .clone();
+                limits.push((
+                    LimitConstraint::IsolationDebtCeiling,
+                    (isolated_pool_parameters.isolation_debt_ceiling
+                        - isolated_pool_parameters.get_isolation_debt_value(prices))
+                        / cost_of_asset_in_terms_of_xrd,
+                ));
+            }
+            limits
         }
 
-        fn is_authorized(&mut self, admin_badge: Proof) -> bool {
-            let manager = ResourceManager::from(admin_badge.resource_address());
-            let non_fungible_id = admin_badge
-                .check(manager.address())
-                .as_non_fungible()
-                .non_fungible_local_id();
+        // Deposits of the user in isolated pools
+        fn get_isolated_collateral(&self, user: &UserData) -> Vec<ResourceAddress> {
+            user.deposits
+                .iter()
+                .filter(|(resource_address, sd_balance)| {
+                    **sd_balance > Decimal::ZERO
+                        && self
+                            .pool_parameters
+                            .get(*resource_address)
+                            .unwrap()
+                            .isolated
+                })
+                .map(|(resource_address, _)| *resource_address)
+                .collect()
+        }
 
-            if self.admin_blacklist.contains(&non_fungible_id)
-                || self.admin_signature_check.len() < 3
-            {
-                return false;
-            } else {
-                return true;
+        // Counts the open loans of the user, interest included, against the debt ceiling of
+        // the isolated collateral. Loans no longer backed by isolated collateral are released.
+        fn update_isolation_debt(&mut self, user: &mut UserData) {
+            let isolated_asset_addresses = self.get_isolated_collateral(user);
+            if isolated_asset_addresses.is_empty() && user.isolation_debt.is_empty() {
+                return;
+            }
+            let debts = user.get_debts(&self.get_accrued_pool_parameters());
+            let isolation_debt = isolated_asset_addresses
+                .into_iter()
+                .filter(|_| !debts.is_empty())
+                .map(|isolated_asset_address| (isolated_asset_address, debts.clone()))
+                .collect();
+            for (isolated_asset_address, changes) in user.on_isolation_debt_update(isolation_debt) {
+                let mut pool_parameters = self
+                    .pool_parameters
+                    .get_mut(&isolated_asset_address)
+                    .unwrap();
+                for (resource_address, change) in changes {
+                    pool_parameters.on_isolation_debt_change(resource_address, change);
+                }
             }
         }
 
-        pub fn update_pool_parameters(
-            &mut self,
+        // Checks of `withdraw`, as max amounts of the asset
+        fn get_withdraw_limits(
+            &self,
+            user: &UserData,
             resource_address: ResourceAddress,
-            liquidation_reserve_factor: Decimal,
-            liquidation_bonus: Decimal,
-            max_liquidation_percent: Decimal,
-            max_borrow_percent: Decimal,
-            min_collateral_ratio: Decimal,
-            pool_reserve: Decimal,
-            pool_deposit_limit: Decimal,
-            admin_badge: Proof,
-        ) {
-            let is_admin_authorized = self.is_authorized(admin_badge);
-            if is_admin_authorized == false {
-                panic!("Not authorized")
+            pools: &IndexMap<ResourceAddress, PoolParameters>,
+        ) -> Vec<(LimitConstraint, Decimal)> {
+            let pool_parameters = pools.get(&resource_address).unwrap();
+            let available_liquidity = self.available_liquidity(
+                pool_parameters.deposit_balance,
+                pool_parameters.get_total_borrow_balance(),
+                pool_parameters.reserve_balance,
+                pool_parameters.pool_reserve,
+            );
+            let user_deposit_balance = calculate_deposit_value(
+                user.get_deposit(resource_address),
+                pool_parameters.deposit_balance,
+                pool_parameters.sd_balance,
+            );
+            let user_borrow = calculate_debt_value(
+                user.get_borrow(resource_address),
+                pool_parameters.borrow_balance,
+                pool_parameters.sb_balance,
+            ) + user.get_stable_borrow(resource_address)
+                + user.get_fixed_principal(resource_address);
+            let max_withdraw = calculate_max_borrow_percent_withdraw(
+                pool_parameters.deposit_balance,
+                pool_parameters.max_borrow_percent,
+                user_borrow,
+            );
+            let mut limits = vec![
+                (LimitConstraint::AvailableLiquidity, available_liquidity),
+                (LimitConstraint::DepositBalance, user_deposit_balance),
+                (LimitConstraint::MaxBorrowPercent, max_withdraw),
+            ];
+            // Deposits of an asset with 0 LTV are not collateral
+            let ltv_ratio = self.get_ltv_ratio(user, resource_address);
+            if ltv_ratio > Decimal::ZERO {
+                let prices = self.get_prices();
+                let cost_of_asset_in_terms_of_xrd = *prices.get(&resource_address).unwrap();
+                let e_mode_category = self.get_user_e_mode_category(user);
+                let (collateral, loan) = user.calculate_total_collateral_and_loan(
+                    pools,
+                    &self.ltv_ratios,
+                    e_mode_category.as_ref(),
+                    prices,
+                );
+                limits.push((
+                    LimitConstraint::CollateralHeadroom,
+                    calculate_withdraw_headroom(
+                        collateral,
+                        loan,
+                        ltv_ratio,
+                        cost_of_asset_in_terms_of_xrd,
+                    ),
+                ));
             }
+            limits
+        }
+
+        fn get_token_prices(&self, resource_address: ResourceAddress) -> (Decimal, Decimal) {
             self.pool_parameters
-                .get_mut(&resource_address)
-                .unwrap()
-                .update_pool_parameters(
-                    liquidation_reserve_factor,
-                    liquidation_bonus,
-                    max_liquidation_percent,
-                    max_borrow_percent,
-                    min_collateral_ratio,
-                    pool_reserve,
-                    pool_deposit_limit,
-                );
+                .get(&resource_address)
+                .expect("Pool does not exist for this resource address.")
+                .get_token_prices()
+        }
 
-            self.admin_signature_check = HashMap::new();
+        fn emit_pool_parameters_update(&self, resource_address: ResourceAddress) {
+            let pool_parameters = self
+                .pool_parameters
+                .get(&resource_address)
+                .expect("Pool does not exist for this resource address.")
+                .clone();
+            Runtime::emit_event(PoolParametersUpdateEvent {
+                resource_address,
+                pool_parameters,
+            });
         }
 
-        pub fn update_pool_settings(
-            &mut self,
-            resource_address: ResourceAddress,
-            base: Decimal,
-            base_multiplier: Decimal,
-            multiplier: Decimal,
-            kink: Decimal,
-            reserve_factor: Decimal,
-            ltv_ratio: Decimal,
-            admin_badge: Proof,
-        ) {
-            let is_admin_authorized = self.is_authorized(admin_badge);
-            if is_admin_authorized == false {
-                panic!("Not authorized")
+        // Copies of the parameters of all pools with interest accrued until now, for
+        // computations that must not change the stored pools
+        fn get_accrued_pool_parameters(&self) -> IndexMap<ResourceAddress, PoolParameters> {
+            self.ltv_ratios
+                .keys()
+                .map(|resource_address| {
+                    let mut pool_parameters =
+                        self.pool_parameters.get(resource_address).unwrap().clone();
+                    pool_parameters.accrue_interest();
+                    (*resource_address, pool_parameters)
+                })
+                .collect()
+        }
+
+        fn accrue_user_pools(&mut self, user: &UserData) {
+            let mut resource_addresses: IndexSet<ResourceAddress> =
+                user.deposits.keys().cloned().collect();
+            resource_addresses.extend(user.borrows.keys().cloned());
+            resource_addresses.extend(user.stable_borrows.keys().cloned());
+            resource_addresses.extend(user.fixed_loans.values().map(|loan| loan.resource_address));
+            for resource_address in resource_addresses {
+                self.accrue_pool_interest(resource_address);
             }
-            self.pool_parameters
-                .get_mut(&resource_address)
-                .unwrap()
-                .update_pool_settings(
-                    base,
-                    base_multiplier,
-                    multiplier,
-                    kink,
-                    reserve_factor,
-                    ltv_ratio,
-                );
-            self.ltv_ratios.insert(resource_address, ltv_ratio);
-            self.admin_signature_check = HashMap::new();
         }
 
-        fn update_pool_balances(
-            &mut self,
-            resource_address: ResourceAddress,
-            deposit: Decimal,
-            sd_balance: Decimal,
-            borrow: Decimal,
-            sb_balance: Decimal,
-            reserve: Decimal,
-        ) {
-            self.pool_parameters
-                .get_mut(&resource_address)
-                .unwrap()
-                .update_balances(deposit, sd_balance, borrow, sb_balance, reserve);
+        // The Pool component keeps a copy of the pool balances for readers of the ledger.
+        // Protocol owns the accounting: balances are updated in `PoolParameters` first and
+        // the Pool copy is only ever written from them, together with the token movement.
+        // The deployed Pool does not expose its vault amount, so the protocol counts it.
+        fn pool_take(&mut self, resource_address: ResourceAddress, amount: Decimal) -> Bucket {
+            let pool_parameters = self.pool_parameters.get(&resource_address).unwrap().clone();
+            let mut pool = *self.pools.get(&resource_address).unwrap();
+            let non_fungible_local_ids: IndexSet<NonFungibleLocalId> =
+                self.protocol_badge.non_fungible_local_ids(1);
+            let bucket =
+                self.protocol_badge
+                    .authorize_with_non_fungibles(&non_fungible_local_ids, || {
+                        pool.take(
+                            amount,
+                            pool_parameters.deposit_balance,
+                            pool_parameters.sd_balance,
+                            pool_parameters.borrow_balance,
+                            pool_parameters.sb_balance,
+                            pool_parameters.reserve_balance,
+                        )
+                    });
+            let mut pool_parameters = self.pool_parameters.get_mut(&resource_address).unwrap();
+            pool_parameters.vault_balance -= bucket.amount();
+            pool_parameters.assert_no_deficit();
+            bucket
+        }
+
+        fn pool_put(&mut self, resource_address: ResourceAddress, bucket: Bucket) {
+            let pool_parameters = {
+                let mut pool_parameters =
+                    self.pool_parameters.get_mut(&resource_address).unwrap();
+                pool_parameters.vault_balance += bucket.amount();
+                pool_parameters.assert_no_deficit();
+                pool_parameters.clone()
+            };
+            let mut pool = *self.pools.get(&resource_address).unwrap();
+            let non_fungible_local_ids: IndexSet<NonFungibleLocalId> =
+                self.protocol_badge.non_fungible_local_ids(1);
+            self.protocol_badge
+                .authorize_with_non_fungibles(&non_fungible_local_ids, || {
+                    pool.put(
+                        bucket,
+                        pool_parameters.deposit_balance,
+                        pool_parameters.sd_balance,
+                        pool_parameters.borrow_balance,
+                        pool_parameters.sb_balance,
+                        pool_parameters.reserve_balance,
+                    )
+                });
+        }
+
+        // Writes the balances to the Pool component without moving tokens
+        fn push_pool_balances(&mut self, resource_address: ResourceAddress) {
+            let bucket = ResourceManager::from(resource_address).create_empty_bucket();
+            self.pool_put(resource_address, bucket);
         }
 
-        pub fn update_balances(
+        fn update_pool_balances(
             &mut self,
             resource_address: ResourceAddress,
             deposit: Decimal,
@@ -1309,17 +3075,11 @@ mod lending_protocol {
             borrow: Decimal,
             sb_balance: Decimal,
             reserve: Decimal,
-            admin_badge: Proof,
         ) {
-            let is_admin_authorized = self.is_authorized(admin_badge);
-            if is_admin_authorized == false {
-                panic!("Not authorized")
-            }
             self.pool_parameters
                 .get_mut(&resource_address)
                 .unwrap()
                 .update_balances(deposit, sd_balance, borrow, sb_balance, reserve);
-            self.admin_signature_check = HashMap::new();
         }
 
         pub fn lock_pool(
@@ -1339,19 +3099,42 @@ mod lending_protocol {
                 .get_mut(&resource_address)
                 .unwrap()
                 .lock_pool(deposit_locked, borrow_locked, withdraw_locked, repay_locked);
+            Runtime::emit_event(PoolLockEvent {
+                resource_address,
+                deposit_locked,
+                borrow_locked,
+                withdraw_locked,
+                repay_locked,
+            });
             self.admin_signature_check = HashMap::new();
         }
 
+        fn get_prices(&self) -> HashMap<ResourceAddress, Decimal> {
+            let mut oracle = self.oracle_address;
+            let mut prices = HashMap::new();
+            for (&res_address, &_ratio) in &self.ltv_ratios {
+                let mut price_in_xrd = Decimal::ONE;
+                if res_address != XRD {
+                    price_in_xrd = oracle.get_price_in_xrd(res_address);
+                }
+                prices.insert(res_address, price_in_xrd);
+            }
+            prices
+        }
+
         fn available_liquidity(
-            &mut self,
+            &self,
             total_deposit: Decimal,
             total_borrow: Decimal,
             reserve_balance: Decimal,
             pool_reserve: Decimal,
         ) -> Decimal {
-            let available_pool_amount =
-                total_deposit - reserve_balance - total_deposit * pool_reserve - total_borrow;
-            available_pool_amount
+            calculate_available_liquidity(
+                total_deposit,
+                total_borrow,
+                reserve_balance,
+                pool_reserve,
+            )
         }
     }
 }
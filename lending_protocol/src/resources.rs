@@ -15,6 +15,13 @@ pub struct UserBadge {
     pub name: String,
 }
 
+#[derive(ScryptoSbor, NonFungibleData)]
+pub struct FlashLoanReceipt {
+    pub resource_address: ResourceAddress,
+    pub amount: Decimal,
+    pub fee: Decimal,
+}
+
 pub fn create_admin_badge(
     protocol_rule: AccessRule,
     component_rule: AccessRule,
@@ -136,3 +143,99 @@ pub fn create_user_resource_manager(
         .create_with_no_initial_supply()
         .into()
 }
+
+pub fn create_flash_loan_receipt_manager(
+    protocol_rule: AccessRule,
+    component_rule: AccessRule,
+    admin_rule: AccessRule,
+) -> NonFungibleResourceManager {
+    ResourceBuilder::new_ruid_non_fungible::<FlashLoanReceipt>(OwnerRole::None)
+        .metadata(metadata!(
+            roles {
+                metadata_setter => admin_rule.clone();
+                metadata_setter_updater => admin_rule.clone();
+                metadata_locker => admin_rule.clone();
+                metadata_locker_updater => admin_rule.clone();
+            },
+            init {
+                "name" => "Flash Loan Receipt", locked;
+            }
+        ))
+        .mint_roles(mint_roles! {
+          minter => component_rule.clone();
+          minter_updater => protocol_rule.clone();
+        })
+        .burn_roles(burn_roles! {
+          burner => component_rule.clone();
+          burner_updater => protocol_rule.clone();
+        })
+        // Receipt can not be deposited, so the loan has to be repaid in the same transaction
+        .deposit_roles(deposit_roles! {
+          depositor => rule!(deny_all);
+          depositor_updater => rule!(deny_all);
+        })
+        .create_with_no_initial_supply()
+        .into()
+}
+
+#[derive(ScryptoSbor, NonFungibleData)]
+pub struct UnstakeClaim {
+    /// Safety module stake tokens waiting for the cooldown
+    pub stake_amount: Decimal,
+    /// Epoch the claim can be redeemed from
+    pub claimable_at: u64,
+}
+
+pub fn create_safety_module_stake_manager(
+    protocol_rule: AccessRule,
+    component_rule: AccessRule,
+) -> FungibleResourceManager {
+    ResourceBuilder::new_fungible(OwnerRole::None)
+        .metadata(metadata!(
+            roles {
+                metadata_setter => protocol_rule.clone();
+                metadata_setter_updater => protocol_rule.clone();
+                metadata_locker => protocol_rule.clone();
+                metadata_locker_updater => protocol_rule.clone();
+            },
+            init {
+                "name" => "Safety Module Stake", locked;
+            }
+        ))
+        .mint_roles(mint_roles! {
+          minter => component_rule.clone();
+          minter_updater => protocol_rule.clone();
+        })
+        .burn_roles(burn_roles! {
+          burner => component_rule.clone();
+          burner_updater => protocol_rule.clone();
+        })
+        .create_with_no_initial_supply()
+}
+
+pub fn create_unstake_claim_manager(
+    protocol_rule: AccessRule,
+    component_rule: AccessRule,
+) -> NonFungibleResourceManager {
+    ResourceBuilder::new_ruid_non_fungible::<UnstakeClaim>(OwnerRole::None)
+        .metadata(metadata!(
+            roles {
+                metadata_setter => protocol_rule.clone();
+                metadata_setter_updater => protocol_rule.clone();
+                metadata_locker => protocol_rule.clone();
+                metadata_locker_updater => protocol_rule.clone();
+            },
+            init {
+                "name" => "Safety Module Unstake Claim", locked;
+            }
+        ))
+        .mint_roles(mint_roles! {
+          minter => component_rule.clone();
+          minter_updater => protocol_rule.clone();
+        })
+        .burn_roles(burn_roles! {
+          burner => component_rule.clone();
+          burner_updater => protocol_rule.clone();
+        })
+        .create_with_no_initial_supply()
+}
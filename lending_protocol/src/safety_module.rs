@@ -0,0 +1,192 @@
+use scrypto::prelude::*;
+
+use crate::calculations::*;
+use crate::events::*;
+use crate::resources::*;
+
+#[blueprint]
+#[events(
+    StakeEvent,
+    UnstakeRequestEvent,
+    UnstakeClaimEvent,
+    SafetyModuleRewardEvent,
+    SlashEvent
+)]
+mod safety_module {
+    enable_method_auth! {
+        roles {
+            protocol => updatable_by: [];
+        },
+        methods {
+            stake => PUBLIC;
+            request_unstake => PUBLIC;
+            claim => PUBLIC;
+            get_staked_amount => PUBLIC;
+            get_stake_price => PUBLIC;
+            add_rewards => restrict_to: [protocol];
+            slash => restrict_to: [protocol];
+            update_settings => restrict_to: [protocol];
+        }
+    }
+
+    // Backstop of a single pool asset. Stakers earn part of the pool reserve and can be
+    // slashed to cover bad debt, both change the price of the stake token.
+    struct SafetyModule {
+        staked: Vault,
+        // Stake tokens of unstake requests, still earning rewards and slashable
+        cooldown: FungibleVault,
+        stake_token_manager: FungibleResourceManager,
+        unstake_claim_manager: NonFungibleResourceManager,
+        // Max percent of the staked amount a single slash can take
+        max_slash_percent: Decimal,
+        // Epochs between an unstake request and its claim
+        cooldown_epochs: u64,
+    }
+
+    impl SafetyModule {
+        pub fn instantiate(
+            protocol_rule: AccessRule,
+            resource_address: ResourceAddress,
+            max_slash_percent: Decimal,
+            cooldown_epochs: u64,
+        ) -> Global<SafetyModule> {
+            let (address_reservation, component_address) =
+                Runtime::allocate_component_address(SafetyModule::blueprint_id());
+            let component_rule = rule!(require(global_caller(component_address)));
+            let stake_token_manager =
+                create_safety_module_stake_manager(protocol_rule.clone(), component_rule.clone());
+            let unstake_claim_manager =
+                create_unstake_claim_manager(protocol_rule.clone(), component_rule);
+            Self {
+                staked: Vault::new(resource_address),
+                cooldown: FungibleVault::new(stake_token_manager.address()),
+                stake_token_manager,
+                unstake_claim_manager,
+                max_slash_percent,
+                cooldown_epochs,
+            }
+            .instantiate()
+            .prepare_to_globalize(OwnerRole::None)
+            .roles(roles!(
+                protocol => protocol_rule;
+            ))
+            .with_address(address_reservation)
+            .globalize()
+        }
+
+        pub fn stake(&mut self, asset: Bucket) -> FungibleBucket {
+            assert!(
+                asset.resource_address() == self.staked.resource_address(),
+                "Asset can not be staked in this safety module."
+            );
+            if is_safety_module_drained(self.staked.amount(), self.get_stake_token_supply()) {
+                panic!("Safety module has been fully slashed, staking is closed.");
+            }
+            let amount = asset.amount();
+            let stake_amount =
+                calculate_sd_to_mint(amount, self.staked.amount(), self.get_stake_token_supply());
+            self.staked.put(asset);
+            Runtime::emit_event(StakeEvent {
+                amount,
+                stake_amount,
+            });
+            self.stake_token_manager.mint(stake_amount)
+        }
+
+        /// Starts the cooldown of the stake tokens. The returned claim can be redeemed
+        /// with `claim` once the cooldown is over.
+        pub fn request_unstake(&mut self, stake_tokens: FungibleBucket) -> NonFungibleBucket {
+            assert!(
+                stake_tokens.resource_address() == self.stake_token_manager.address(),
+                "Invalid stake token."
+            );
+            let stake_amount = stake_tokens.amount();
+            let claimable_at = Runtime::current_epoch().number() + self.cooldown_epochs;
+            self.cooldown.put(stake_tokens);
+            Runtime::emit_event(UnstakeRequestEvent {
+                stake_amount,
+                claimable_at,
+            });
+            self.unstake_claim_manager
+                .mint_ruid_non_fungible(UnstakeClaim {
+                    stake_amount,
+                    claimable_at,
+                })
+        }
+
+        pub fn claim(&mut self, unstake_claim: NonFungibleBucket) -> Bucket {
+            assert!(
+                unstake_claim.resource_address() == self.unstake_claim_manager.address(),
+                "Invalid unstake claim."
+            );
+            let claim: UnstakeClaim = unstake_claim.non_fungible::<UnstakeClaim>().data();
+            if !is_unstake_claimable(Runtime::current_epoch().number(), claim.claimable_at) {
+                panic!("Unstake claim is claimable at epoch {}", claim.claimable_at);
+            }
+            let amount = calculate_deposit_value(
+                claim.stake_amount,
+                self.staked.amount(),
+                self.get_stake_token_supply(),
+            );
+            unstake_claim.burn();
+            self.cooldown.take(claim.stake_amount).burn();
+            Runtime::emit_event(UnstakeClaimEvent {
+                amount,
+                stake_amount: claim.stake_amount,
+            });
+            self.staked
+                .take_advanced(amount, WithdrawStrategy::Rounded(RoundingMode::ToZero))
+        }
+
+        pub fn get_staked_amount(&self) -> Decimal {
+            self.staked.amount()
+        }
+
+        /// Staked asset per stake token
+        pub fn get_stake_price(&self) -> Decimal {
+            calculate_token_price(self.staked.amount(), self.get_stake_token_supply())
+        }
+
+        /// Adds the rewards to the staked amount. Rewards are returned while nobody stakes,
+        /// the first staker would take them all otherwise.
+        pub fn add_rewards(&mut self, rewards: Bucket) -> Option<Bucket> {
+            if self.get_stake_token_supply() == Decimal::ZERO {
+                return Some(rewards);
+            }
+            let amount = rewards.amount();
+            self.staked.put(rewards);
+            Runtime::emit_event(SafetyModuleRewardEvent {
+                amount,
+                staked_amount: self.staked.amount(),
+            });
+            None
+        }
+
+        pub fn slash(&mut self, amount: Decimal) -> Bucket {
+            let max_slash_amount =
+                calculate_max_slash_amount(self.staked.amount(), self.max_slash_percent);
+            if amount > max_slash_amount {
+                panic!("Max slash amount is {}", max_slash_amount);
+            }
+            let slashed = self
+                .staked
+                .take_advanced(amount, WithdrawStrategy::Rounded(RoundingMode::ToZero));
+            Runtime::emit_event(SlashEvent {
+                amount: slashed.amount(),
+                staked_amount: self.staked.amount(),
+            });
+            slashed
+        }
+
+        pub fn update_settings(&mut self, max_slash_percent: Decimal, cooldown_epochs: u64) {
+            self.max_slash_percent = max_slash_percent;
+            self.cooldown_epochs = cooldown_epochs;
+        }
+
+        fn get_stake_token_supply(&self) -> Decimal {
+            self.stake_token_manager
+                .total_supply()
+                .unwrap_or(Decimal::ZERO)
+        }
+    }
+}
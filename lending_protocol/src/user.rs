@@ -2,8 +2,206 @@ use core::panic;
 
 use scrypto::prelude::*;
 
+use crate::calculations::*;
+use crate::e_mode::EModeCategory;
 use crate::pool_parameters::PoolParameters;
 
+/// Stable rate loan of a single asset
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct StableBorrow {
+    /// Debt including interest accrued until `updated_at`
+    pub amount: Decimal,
+
+    /// Rate locked when the loan was taken or last re-priced
+    pub rate: Decimal,
+
+    /// Timestamp (seconds) of the last update
+    pub updated_at: i64,
+}
+
+impl StableBorrow {
+    pub fn get_amount(&self, now: i64) -> Decimal {
+        self.amount
+            + calculate_debt_interest(
+                self.amount,
+                calculate_compounded_rate(self.rate, now - self.updated_at),
+            )
+    }
+}
+
+/// Fixed-rate loan that has to be repaid by its maturity epoch
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct FixedLoan {
+    /// Borrowed asset
+    pub resource_address: ResourceAddress,
+
+    /// Part of the borrowed amount that is not repaid yet
+    pub principal: Decimal,
+
+    /// Debt including interest accrued until `updated_at`
+    pub amount: Decimal,
+
+    /// Rate fixed when the loan was taken
+    pub rate: Decimal,
+
+    /// Epoch the loan was taken at
+    pub started_at: u64,
+
+    /// Epoch the loan has to be repaid by
+    pub maturity: u64,
+
+    /// Estimated timestamp (seconds) of the maturity epoch, the penalty rate accrues from it
+    pub maturity_at: i64,
+
+    /// Penalty rate of the pool when the loan was taken, added to the rate after maturity
+    pub penalty_rate: Decimal,
+
+    /// Timestamp (seconds) of the last update
+    pub updated_at: i64,
+
+    /// Timestamp (seconds) the loan was marked overdue, 0 until then
+    pub overdue_since: i64,
+}
+
+impl FixedLoan {
+    /// Accrues the loan rate until `maturity_at` and the loan rate plus the penalty rate after it
+    pub fn get_amount(&self, now: i64) -> Decimal {
+        let penalty_from = self
+            .maturity_at
+            .clamp(self.updated_at, now.max(self.updated_at));
+        let amount = self.amount
+            + calculate_debt_interest(
+                self.amount,
+                calculate_compounded_rate(self.rate, penalty_from - self.updated_at),
+            );
+        amount
+            + calculate_debt_interest(
+                amount,
+                calculate_compounded_rate(self.rate + self.penalty_rate, now - penalty_from),
+            )
+    }
+
+    pub fn is_overdue(&self) -> bool {
+        Runtime::current_epoch().number() > self.maturity
+    }
+}
+
+/// Health of a user's position, values in XRD
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct UserHealth {
+    /// Deposits weighted by the LTV ratio of their asset
+    pub collateral_value: Decimal,
+    pub debt_value: Decimal,
+    /// Debt value divided by collateral value
+    pub loan_limit_used: Decimal,
+    /// Lowest min collateral ratio of the deposited assets, the position can be
+    /// liquidated once the loan limit used is above it
+    pub liquidation_threshold: Decimal,
+    /// Liquidation threshold divided by loan limit used, below 1 the position can be liquidated
+    pub health_factor: Decimal,
+    /// Drop of all collateral prices that would make the position liquidatable
+    pub liquidation_price_drop: Decimal,
+}
+
+/// Outcome of a liquidation simulated by `simulate_liquidation`
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct LiquidationSimulation {
+    /// Deposit asset paid to the liquidator
+    pub reward: Decimal,
+    /// Deposit asset added to the reserve
+    pub platform_bonus: Decimal,
+    /// Health of the position after the liquidation
+    pub health: UserHealth,
+}
+
+/// Check limiting the amount of a borrow or withdrawal
+#[derive(ScryptoSbor, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitConstraint {
+    /// Borrowing or withdrawing is locked for the pool
+    PoolLocked,
+    /// Pool liquidity not lent out, less the reserve and the pool reserve percent
+    AvailableLiquidity,
+    /// Deposit of the user in the asset
+    DepositBalance,
+    /// Max borrow percent of the pool deposits
+    MaxBorrowPercent,
+    /// Borrow cap of the pool, less its total borrow balance
+    BorrowCap,
+    /// Asset can not be borrowed against isolated collateral
+    IsolatedCollateral,
+    /// Debt ceiling of the user's isolated collateral
+    IsolationDebtCeiling,
+    /// LTV-weighted collateral not used by the user's loans
+    CollateralHeadroom,
+    /// Asset is outside the e-mode category of the user
+    EModeCategory,
+}
+
+/// Largest amount allowed by all checks and the check it is limited by
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct MaxAmount {
+    pub amount: Decimal,
+    pub constraint: LimitConstraint,
+}
+
+impl MaxAmount {
+    pub fn from_limits(limits: &[(LimitConstraint, Decimal)]) -> MaxAmount {
+        let (constraint, amount) = select_max_amount(limits);
+        MaxAmount { amount, constraint }
+    }
+
+    pub fn assert_within_limits(
+        action: &str,
+        amount: Decimal,
+        limits: &[(LimitConstraint, Decimal)],
+    ) {
+        for (constraint, limit) in limits {
+            if amount > *limit {
+                panic!(
+                    "Max {} amount is {}, limited by {:?}",
+                    action,
+                    (*limit).max(Decimal::ZERO),
+                    constraint
+                );
+            }
+        }
+    }
+}
+
+/// Reason a liquidation would fail
+#[derive(ScryptoSbor, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LiquidationError {
+    NoDeposit,
+    NoBorrow,
+    PositionHealthy,
+    AuctionNotStarted,
+    AmountAboveBorrowBalance,
+    AmountAboveAvailableLiquidity,
+    AmountAboveMaxRepayment,
+    AmountAboveMaxLiquidatingAmount,
+}
+
+impl LiquidationError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            LiquidationError::NoDeposit => "User deposit balance of selected token is 0.",
+            LiquidationError::NoBorrow => "No borrow from the user",
+            LiquidationError::PositionHealthy => "Liquidation not allowed.",
+            LiquidationError::AuctionNotStarted => {
+                "Liquidation auction has not started, call update_liquidation_auction first."
+            }
+            LiquidationError::AmountAboveBorrowBalance => "Amount is greater than borrow balance",
+            LiquidationError::AmountAboveAvailableLiquidity => {
+                "Amount is greater than available liquidity"
+            }
+            LiquidationError::AmountAboveMaxRepayment => "Amount is greater than max repayment",
+            LiquidationError::AmountAboveMaxLiquidatingAmount => {
+                "Amount is greater than max liquidating amount"
+            }
+        }
+    }
+}
+
 /// Data describing the user's positions
 #[derive(ScryptoSbor, NonFungibleData, Clone, Debug)]
 pub struct UserData {
@@ -29,6 +227,27 @@ pub struct UserData {
     /// All users borrows.
     #[mutable]
     pub borrows: IndexMap<ResourceAddress, Decimal>,
+
+    /// All users stable rate borrows.
+    #[mutable]
+    pub stable_borrows: IndexMap<ResourceAddress, StableBorrow>,
+
+    /// All users fixed-term loans by loan id.
+    #[mutable]
+    pub fixed_loans: IndexMap<u64, FixedLoan>,
+
+    /// Epoch the liquidation auction of the position started at, 0 while it is healthy.
+    #[mutable]
+    pub liquidation_auction_started_at: u64,
+
+    /// E-mode category the user opted into, 0 for none.
+    #[mutable]
+    pub e_mode_category: u8,
+
+    /// Loans of the user counted against the debt ceiling of each isolated asset, in units
+    /// of each borrowed asset.
+    #[mutable]
+    pub isolation_debt: IndexMap<ResourceAddress, IndexMap<ResourceAddress, Decimal>>,
 }
 
 impl UserData {
@@ -40,6 +259,91 @@ impl UserData {
         Self::get_value(&self.borrows, resource_address)
     }
 
+    /// Stable rate debt including interest accrued until now
+    pub fn get_stable_borrow(&self, resource_address: ResourceAddress) -> Decimal {
+        self.stable_borrows
+            .get(&resource_address)
+            .map(|stable_borrow| stable_borrow.get_amount(get_current_time()))
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    /// Principal of all fixed-term loans of the asset not repaid yet
+    pub fn get_fixed_principal(&self, resource_address: ResourceAddress) -> Decimal {
+        self.fixed_loans
+            .values()
+            .filter(|loan| loan.resource_address == resource_address)
+            .fold(Decimal::ZERO, |principal, loan| principal + loan.principal)
+    }
+
+    /// Debt of all fixed-term loans of the asset including interest accrued until now
+    pub fn get_fixed_borrow(&self, resource_address: ResourceAddress) -> Decimal {
+        let now = get_current_time();
+        self.fixed_loans
+            .values()
+            .filter(|loan| loan.resource_address == resource_address)
+            .fold(Decimal::ZERO, |debt, loan| debt + loan.get_amount(now))
+    }
+
+    /// Variable, stable and fixed-term debt of each borrowed asset including interest,
+    /// in units of the asset
+    pub fn get_debts(
+        &self,
+        pool_parameters: &IndexMap<ResourceAddress, PoolParameters>,
+    ) -> IndexMap<ResourceAddress, Decimal> {
+        self.get_borrowed_assets()
+            .into_iter()
+            .map(|resource_address| {
+                let parameters = pool_parameters.get(&resource_address).unwrap();
+                let debt = calculate_debt_value(
+                    self.get_borrow(resource_address),
+                    parameters.borrow_balance,
+                    parameters.sb_balance,
+                ) + self.get_stable_borrow(resource_address)
+                    + self.get_fixed_borrow(resource_address);
+                (resource_address, debt)
+            })
+            .filter(|(_, debt)| *debt > Decimal::ZERO)
+            .collect()
+    }
+
+    /// Replaces the loans counted against the debt ceiling of each isolated asset. Returns
+    /// the change of the counted amount of each borrowed asset, per isolated asset.
+    pub fn on_isolation_debt_update(
+        &mut self,
+        isolation_debt: IndexMap<ResourceAddress, IndexMap<ResourceAddress, Decimal>>,
+    ) -> IndexMap<ResourceAddress, IndexMap<ResourceAddress, Decimal>> {
+        let changes = calculate_isolation_debt_changes(&self.isolation_debt, &isolation_debt);
+        self.isolation_debt = isolation_debt;
+        changes
+    }
+
+    /// Assets of all variable, stable and fixed-term loans of the user
+    pub fn get_borrowed_assets(&self) -> IndexSet<ResourceAddress> {
+        let mut resource_addresses: IndexSet<ResourceAddress> = self
+            .borrows
+            .iter()
+            .filter(|(_, sb_balance)| **sb_balance > Decimal::ZERO)
+            .map(|(resource_address, _)| *resource_address)
+            .collect();
+        resource_addresses.extend(self.stable_borrows.keys().cloned());
+        resource_addresses.extend(self.fixed_loans.values().map(|loan| loan.resource_address));
+        resource_addresses
+    }
+
+    pub fn get_stable_rate(&self, resource_address: ResourceAddress) -> Decimal {
+        self.stable_borrows
+            .get(&resource_address)
+            .map(|stable_borrow| stable_borrow.rate)
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    pub fn get_fixed_loan(&self, loan_id: u64) -> FixedLoan {
+        self.fixed_loans
+            .get(&loan_id)
+            .expect("Fixed-term loan does not exist.")
+            .clone()
+    }
+
     fn get_value(map: &IndexMap<ResourceAddress, Decimal>, key: ResourceAddress) -> Decimal {
         map.get(&key).copied().unwrap_or(Decimal::ZERO)
     }
@@ -82,47 +386,201 @@ impl UserData {
         self.update_borrow(resource_address, sb_balance);
     }
 
+    pub fn on_stable_borrow(
+        &mut self,
+        resource_address: ResourceAddress,
+        amount: Decimal,
+        rate: Decimal,
+    ) {
+        let stable_borrow = self.get_stable_borrow(resource_address);
+        let new_stable_borrow = stable_borrow + amount;
+        // Adding to an existing loan blends the locked rates
+        let new_rate = (stable_borrow * self.get_stable_rate(resource_address) + amount * rate)
+            / new_stable_borrow;
+        self.update_stable_borrow(resource_address, new_stable_borrow, new_rate);
+    }
+
+    pub fn on_stable_repay(&mut self, resource_address: ResourceAddress, amount: Decimal) {
+        let mut stable_borrow = self.get_stable_borrow(resource_address);
+        stable_borrow -= amount;
+        if stable_borrow < Decimal::ZERO {
+            stable_borrow = Decimal::ZERO;
+        }
+        let rate = self.get_stable_rate(resource_address);
+        self.update_stable_borrow(resource_address, stable_borrow, rate);
+    }
+
+    pub fn on_stable_rebalance(&mut self, resource_address: ResourceAddress, rate: Decimal) {
+        let stable_borrow = self.get_stable_borrow(resource_address);
+        self.update_stable_borrow(resource_address, stable_borrow, rate);
+    }
+
+    fn update_stable_borrow(
+        &mut self,
+        resource_address: ResourceAddress,
+        amount: Decimal,
+        rate: Decimal,
+    ) {
+        self.stable_borrows.insert(
+            resource_address,
+            StableBorrow {
+                amount,
+                rate,
+                updated_at: get_current_time(),
+            },
+        );
+    }
+
+    pub fn on_fixed_borrow(
+        &mut self,
+        resource_address: ResourceAddress,
+        amount: Decimal,
+        rate: Decimal,
+        penalty_rate: Decimal,
+        maturity: u64,
+    ) -> u64 {
+        let loan_id = self
+            .fixed_loans
+            .keys()
+            .max()
+            .map_or(1, |loan_id| loan_id + 1);
+        let started_at = Runtime::current_epoch().number();
+        let now = get_current_time();
+        self.fixed_loans.insert(
+            loan_id,
+            FixedLoan {
+                resource_address,
+                principal: amount,
+                amount,
+                rate,
+                started_at,
+                maturity,
+                maturity_at: now + (maturity - started_at) as i64 * SECONDS_PER_EPOCH,
+                penalty_rate,
+                updated_at: now,
+                overdue_since: 0,
+            },
+        );
+        loan_id
+    }
+
+    /// Repays interest first, then principal. Returns the interest and principal repaid.
+    pub fn on_fixed_repay(&mut self, loan_id: u64, amount: Decimal) -> (Decimal, Decimal) {
+        let mut loan = self.get_fixed_loan(loan_id);
+        let now = get_current_time();
+        let loan_amount = loan.get_amount(now);
+        if amount > loan_amount {
+            panic!("Amount is greater than loan balance");
+        }
+        let interest = loan_amount - loan.principal;
+        let interest_repaid = amount.min(interest);
+        let principal_repaid = amount - interest_repaid;
+        if amount == loan_amount {
+            self.fixed_loans.shift_remove(&loan_id);
+        } else {
+            loan.principal -= principal_repaid;
+            loan.amount = loan_amount - amount;
+            loan.updated_at = now;
+            self.fixed_loans.insert(loan_id, loan);
+        }
+        (interest_repaid, principal_repaid)
+    }
+
+    /// Marks a loan past its maturity as overdue, the penalty rate accrues from the maturity
+    pub fn mark_fixed_loan_overdue(&mut self, loan_id: u64) {
+        let mut loan = self.get_fixed_loan(loan_id);
+        if !loan.is_overdue() {
+            panic!("Loan is not overdue.");
+        }
+        if loan.overdue_since > 0 {
+            panic!("Loan is already marked overdue.");
+        }
+        let now = get_current_time();
+        loan.amount = loan.get_amount(now);
+        loan.updated_at = now;
+        loan.overdue_since = now;
+        self.fixed_loans.insert(loan_id, loan);
+    }
+
     pub fn on_liquidate_repay(
         &mut self,
         amount: Decimal,
         resource_address: ResourceAddress,
         cost_of_asset_in_terms_of_xrd: Decimal,
-        sb_price: Decimal,
-    ) -> Decimal {
+        pool_parameters: &PoolParameters,
+    ) -> Result<Decimal, LiquidationError> {
         let sb_balance = self.get_borrow(resource_address);
         // Increase borrow balance by interests accrued
-        let mut borrow_balance = sb_balance * sb_price;
+        let mut borrow_balance = calculate_debt_value(
+            sb_balance,
+            pool_parameters.borrow_balance,
+            pool_parameters.sb_balance,
+        );
+        let stable_borrow_balance =
+            self.get_stable_borrow(resource_address) * cost_of_asset_in_terms_of_xrd;
         //TO DO: CALCULATE INTEREST BASED ON TIME ELAPSSED
         let mut interest = Decimal::ZERO;
         interest *= cost_of_asset_in_terms_of_xrd;
         borrow_balance *= cost_of_asset_in_terms_of_xrd;
         // Repay the loan
-        if borrow_balance < amount {
-            panic!("Amount is greater than borrow balance");
+        if borrow_balance + stable_borrow_balance < amount {
+            Err(LiquidationError::AmountAboveBorrowBalance)
         } else {
-            borrow_balance -= amount;
-            borrow_balance /= cost_of_asset_in_terms_of_xrd;
-            self.update_borrow(resource_address.clone(), borrow_balance / sb_price);
-            interest
+            // Stable rate debt is repaid first
+            let stable_repaid = amount.min(stable_borrow_balance);
+            if stable_repaid > Decimal::ZERO {
+                self.on_stable_repay(
+                    resource_address,
+                    stable_repaid / cost_of_asset_in_terms_of_xrd,
+                );
+            }
+            let sb_repaid = calculate_sb_to_burn(
+                (amount - stable_repaid) / cost_of_asset_in_terms_of_xrd,
+                pool_parameters.borrow_balance,
+                pool_parameters.sb_balance,
+            );
+            self.update_borrow(
+                resource_address.clone(),
+                (sb_balance - sb_repaid).max(Decimal::ZERO),
+            );
+            Ok(interest)
         }
     }
 
-    pub fn on_liquidate(
+    /// Repays `amount` of the repaid asset debt and burns the sd tokens of the deposited
+    /// asset paid out as reward and platform bonus. The amount is limited by the max
+    /// liquidation percent (all of it for insolvent positions), the available liquidity of
+    /// the deposited asset pool and the user deposit. Returns the reward, the platform bonus
+    /// and the repaid debt, or the limit exceeded, in which case the user data is unchanged.
+    /// Health and auction checks are left to the caller.
+    pub fn try_on_liquidate(
         &mut self,
         mut amount: Decimal,
         repaid_asset_address: ResourceAddress,
-        borrow_amount: Decimal,
-        deposit_amount: Decimal,
-        mut max_liquidation_percent: Decimal,
-        liquidation_bonus: Decimal,
-        liquidation_reserve_factor: Decimal,
-        mut liquidated_user_deposit_balance: Decimal,
         deposit_asset_address: ResourceAddress,
+        pool_parameters: &IndexMap<ResourceAddress, PoolParameters>,
         prices: HashMap<ResourceAddress, Decimal>,
-        available_liquidity: Decimal,
-        sb_price: Decimal,
-        sd_price: Decimal,
-    ) -> (Decimal, Decimal, Decimal) {
+        e_mode_category: Option<&EModeCategory>,
+    ) -> Result<(Decimal, Decimal, Decimal), LiquidationError> {
+        let repaid_pool_parameters = pool_parameters.get(&repaid_asset_address).unwrap();
+        let deposit_pool_parameters = pool_parameters.get(&deposit_asset_address).unwrap();
+        let max_liquidation_percent = repaid_pool_parameters.max_liquidation_percent;
+        let liquidation_reserve_factor = repaid_pool_parameters.liquidation_reserve_factor;
+        let (deposit_amount, borrow_amount) =
+            self.get_deposit_and_borrow_balance_in_xrd(pool_parameters, &prices);
+        let available_liquidity = calculate_available_liquidity(
+            deposit_pool_parameters.deposit_balance,
+            deposit_pool_parameters.get_total_borrow_balance(),
+            deposit_pool_parameters.reserve_balance,
+            deposit_pool_parameters.pool_reserve,
+        );
+        let mut liquidated_user_deposit_balance = self.get_deposit(deposit_asset_address);
+        let liquidation_bonus = Self::get_liquidation_bonus(
+            self.liquidation_auction_started_at,
+            deposit_asset_address,
+            repaid_pool_parameters,
+            e_mode_category,
+        );
         let cost_of_deposit_asset_in_terms_of_xrd = prices
             .get(&deposit_asset_address)
             .expect("Price for deposit asset address not found in prices map");
@@ -131,20 +589,26 @@ impl UserData {
             .expect("Price for repaid asset address not found in prices map");
         let available_liquidity_in_terms_of_xrd =
             available_liquidity * *cost_of_deposit_asset_in_terms_of_xrd;
-        //for unsolvent users
-        if borrow_amount * (1 + liquidation_bonus) > deposit_amount {
-            max_liquidation_percent = Decimal::ONE;
-        }
         // Calculate the max repayment amount that's going to be used to repay users debt
-        let max_repayment = max_liquidation_percent * borrow_amount;
+        let max_repayment = calculate_max_liquidation_percent(
+            borrow_amount,
+            deposit_amount,
+            liquidation_bonus,
+            max_liquidation_percent,
+        ) * borrow_amount;
         amount *= *cost_of_repaid_asset_in_terms_of_xrd;
         if amount > available_liquidity_in_terms_of_xrd / (1 + liquidation_bonus) {
-            panic!("Amount is greater than available liquidity");
+            return Err(LiquidationError::AmountAboveAvailableLiquidity);
         }
-        liquidated_user_deposit_balance *= *cost_of_deposit_asset_in_terms_of_xrd * sd_price;
+        let liquidated_user_sd_balance = liquidated_user_deposit_balance;
+        liquidated_user_deposit_balance = calculate_deposit_value(
+            liquidated_user_sd_balance,
+            deposit_pool_parameters.deposit_balance,
+            deposit_pool_parameters.sd_balance,
+        ) * *cost_of_deposit_asset_in_terms_of_xrd;
 
         if amount > max_repayment {
-            panic!("Amount is greater than max repayment");
+            return Err(LiquidationError::AmountAboveMaxRepayment);
         }
 
         let max_liquidating_amount = liquidated_user_deposit_balance
@@ -152,15 +616,15 @@ impl UserData {
                 + (1 + liquidation_bonus * (1 - liquidation_reserve_factor)));
 
         if amount > max_liquidating_amount {
-            panic!("Amount is greater than max liquidating amount");
+            return Err(LiquidationError::AmountAboveMaxLiquidatingAmount);
         }
 
         let interest = self.on_liquidate_repay(
             amount,
             repaid_asset_address,
             *cost_of_repaid_asset_in_terms_of_xrd,
-            sb_price,
-        );
+            repaid_pool_parameters,
+        )?;
 
         // Platform is getting the liquidation fee
         let mut platform_bonus = amount * liquidation_bonus * liquidation_reserve_factor;
@@ -168,18 +632,130 @@ impl UserData {
         let mut reward = amount * (1 + liquidation_bonus * (1 - liquidation_reserve_factor));
         reward /= *cost_of_deposit_asset_in_terms_of_xrd;
         platform_bonus /= *cost_of_deposit_asset_in_terms_of_xrd;
-        liquidated_user_deposit_balance /= *cost_of_deposit_asset_in_terms_of_xrd;
-        let users_new_deposit_balance = liquidated_user_deposit_balance - reward - platform_bonus;
-        self.update_deposit(deposit_asset_address, users_new_deposit_balance / sd_price);
+        let sd_burned = calculate_sd_to_burn(
+            reward + platform_bonus,
+            deposit_pool_parameters.deposit_balance,
+            deposit_pool_parameters.sd_balance,
+        );
+        self.update_deposit(
+            deposit_asset_address,
+            liquidated_user_sd_balance - sd_burned,
+        );
         let mut decreased_amount = amount - interest;
         decreased_amount /= *cost_of_repaid_asset_in_terms_of_xrd;
-        (reward, platform_bonus, decreased_amount)
+        Ok((reward, platform_bonus, decreased_amount))
     }
 
-    pub fn calculate_total_collateral_and_loan(
+    pub fn on_liquidate_fixed_loan(
+        &mut self,
+        loan_id: u64,
+        amount: Decimal,
+        liquidation_reserve_factor: Decimal,
+        deposit_asset_address: ResourceAddress,
+        prices: HashMap<ResourceAddress, Decimal>,
+        available_liquidity: Decimal,
+        repaid_pool_parameters: &PoolParameters,
+        deposit_pool_parameters: &PoolParameters,
+        e_mode_category: Option<&EModeCategory>,
+    ) -> (Decimal, Decimal, Decimal, Decimal) {
+        let loan = self.get_fixed_loan(loan_id);
+        // The auction of an overdue loan runs from its maturity
+        let auction_started_at = if loan.is_overdue() {
+            loan.maturity
+        } else {
+            self.liquidation_auction_started_at
+        };
+        let liquidation_bonus = Self::get_liquidation_bonus(
+            auction_started_at,
+            deposit_asset_address,
+            repaid_pool_parameters,
+            e_mode_category,
+        );
+        let cost_of_deposit_asset_in_terms_of_xrd = prices
+            .get(&deposit_asset_address)
+            .expect("Price for deposit asset address not found in prices map");
+        let cost_of_repaid_asset_in_terms_of_xrd = prices
+            .get(&loan.resource_address)
+            .expect("Price for repaid asset address not found in prices map");
+        let (interest_repaid, principal_repaid) = self.on_fixed_repay(loan_id, amount);
+
+        let amount_in_xrd = amount * *cost_of_repaid_asset_in_terms_of_xrd;
+        // Platform is getting the liquidation fee
+        let platform_bonus = amount_in_xrd * liquidation_bonus * liquidation_reserve_factor
+            / *cost_of_deposit_asset_in_terms_of_xrd;
+        // Liquidator is getting the repaid value and the liquidation reward
+        let reward = amount_in_xrd * (1 + liquidation_bonus * (1 - liquidation_reserve_factor))
+            / *cost_of_deposit_asset_in_terms_of_xrd;
+        if reward + platform_bonus > available_liquidity {
+            panic!("Amount is greater than available liquidity");
+        }
+        let sd_balance = self.get_deposit(deposit_asset_address);
+        let deposit_balance = calculate_deposit_value(
+            sd_balance,
+            deposit_pool_parameters.deposit_balance,
+            deposit_pool_parameters.sd_balance,
+        );
+        if reward + platform_bonus > deposit_balance {
+            panic!("Amount is greater than max liquidating amount");
+        }
+        let sd_burned = calculate_sd_to_burn(
+            reward + platform_bonus,
+            deposit_pool_parameters.deposit_balance,
+            deposit_pool_parameters.sd_balance,
+        );
+        self.update_deposit(deposit_asset_address, sd_balance - sd_burned);
+        (reward, platform_bonus, interest_repaid, principal_repaid)
+    }
+
+    // Collateral in the user's e-mode category is liquidated with the category bonus
+    fn get_liquidation_bonus(
+        auction_started_at: u64,
+        deposit_asset_address: ResourceAddress,
+        repaid_pool_parameters: &PoolParameters,
+        e_mode_category: Option<&EModeCategory>,
+    ) -> Decimal {
+        match e_mode_category {
+            Some(category) if category.contains(deposit_asset_address) => {
+                category.liquidation_bonus
+            }
+            _ => repaid_pool_parameters.get_liquidation_bonus(auction_started_at),
+        }
+    }
+
+    /// Removes every debt of a position left without collateral. Returns the removed
+    /// sb tokens, stable rate loans and fixed-term loans.
+    pub fn on_resolve_bad_debt(
         &mut self,
-        pool_parameters: &KeyValueStore<ResourceAddress, PoolParameters>,
+    ) -> (
+        IndexMap<ResourceAddress, Decimal>,
+        IndexMap<ResourceAddress, StableBorrow>,
+        IndexMap<u64, FixedLoan>,
+    ) {
+        if self
+            .deposits
+            .values()
+            .any(|sd_balance| *sd_balance > Decimal::ZERO)
+        {
+            panic!("User still has collateral.");
+        }
+        let borrows: IndexMap<ResourceAddress, Decimal> = std::mem::take(&mut self.borrows)
+            .into_iter()
+            .filter(|(_, sb_balance)| *sb_balance > Decimal::ZERO)
+            .collect();
+        let stable_borrows = std::mem::take(&mut self.stable_borrows);
+        let fixed_loans = std::mem::take(&mut self.fixed_loans);
+        if borrows.is_empty() && stable_borrows.is_empty() && fixed_loans.is_empty() {
+            panic!("User has no debt.");
+        }
+        self.liquidation_auction_started_at = 0;
+        (borrows, stable_borrows, fixed_loans)
+    }
+
+    pub fn calculate_total_collateral_and_loan(
+        &self,
+        pool_parameters: &IndexMap<ResourceAddress, PoolParameters>,
         ltv_ratios: &HashMap<ResourceAddress, Decimal>,
+        e_mode_category: Option<&EModeCategory>,
         prices: HashMap<ResourceAddress, Decimal>,
     ) -> (Decimal, Decimal) {
         let mut user_collateral_sum: Decimal = 0.into();
@@ -187,45 +763,62 @@ impl UserData {
 
         // Iterate over each asset and calculate the amount of collateral and loan available from each
         for (asset_address, ltv_ratio) in ltv_ratios {
+            // Collateral in the user's e-mode category gets the category LTV
+            let ltv_ratio = match e_mode_category {
+                Some(category) if category.contains(*asset_address) => &category.ltv_ratio,
+                _ => ltv_ratio,
+            };
             let cost_of_asset_in_terms_of_xrd = prices.get(asset_address).unwrap();
             let parameters = pool_parameters.get(asset_address).unwrap().clone();
             let sd_balance = self.get_deposit(asset_address.clone());
             if sd_balance != Decimal::ZERO {
-                let sd_price = parameters.deposit_balance / parameters.sd_balance;
+                let deposit_balance = calculate_deposit_value(
+                    sd_balance,
+                    parameters.deposit_balance,
+                    parameters.sd_balance,
+                );
                 let asset_value_in_xrd =
-                    sd_balance * sd_price * *cost_of_asset_in_terms_of_xrd * *ltv_ratio;
+                    deposit_balance * *cost_of_asset_in_terms_of_xrd * *ltv_ratio;
                 user_collateral_sum += asset_value_in_xrd;
             }
             let sb_balance = self.get_borrow(asset_address.clone());
             if sb_balance != Decimal::ZERO {
-                let sb_price = parameters.borrow_balance / parameters.sb_balance;
-                let asset_loan = sb_balance * sb_price * *cost_of_asset_in_terms_of_xrd;
+                let borrow_balance = calculate_debt_value(
+                    sb_balance,
+                    parameters.borrow_balance,
+                    parameters.sb_balance,
+                );
+                let asset_loan = borrow_balance * *cost_of_asset_in_terms_of_xrd;
                 user_loan_sum += asset_loan;
             }
+            let stable_borrow = self.get_stable_borrow(*asset_address);
+            if stable_borrow != Decimal::ZERO {
+                user_loan_sum += stable_borrow * *cost_of_asset_in_terms_of_xrd;
+            }
         }
+        user_loan_sum += self.get_fixed_loans_in_xrd(&prices);
         (user_collateral_sum, user_loan_sum)
     }
 
     pub fn get_loan_limit_used(
-        &mut self,
-        pool_parameters: &KeyValueStore<ResourceAddress, PoolParameters>,
+        &self,
+        pool_parameters: &IndexMap<ResourceAddress, PoolParameters>,
         ltv_ratios: &HashMap<ResourceAddress, Decimal>,
+        e_mode_category: Option<&EModeCategory>,
         prices: HashMap<ResourceAddress, Decimal>,
     ) -> Decimal {
-        let collateral_and_loan =
-            self.calculate_total_collateral_and_loan(pool_parameters, ltv_ratios, prices);
-        let deposit = collateral_and_loan.0;
-        let loan = collateral_and_loan.1;
-        if loan == Decimal::ZERO {
-            Decimal::ZERO
-        } else {
-            loan / deposit
-        }
+        let collateral_and_loan = self.calculate_total_collateral_and_loan(
+            pool_parameters,
+            ltv_ratios,
+            e_mode_category,
+            prices,
+        );
+        calculate_loan_limit_used(collateral_and_loan.0, collateral_and_loan.1)
     }
 
     pub fn get_deposit_and_borrow_balance_in_xrd(
-        &mut self,
-        pool_parameters: &KeyValueStore<ResourceAddress, PoolParameters>,
+        &self,
+        pool_parameters: &IndexMap<ResourceAddress, PoolParameters>,
         prices: &HashMap<ResourceAddress, Decimal>,
     ) -> (Decimal, Decimal) {
         let mut deposit = Decimal::ZERO;
@@ -235,9 +828,11 @@ impl UserData {
             .for_each(|(_key, value)| {
                 let parameters = pool_parameters.get(&_key).unwrap().clone();
                 let cost_of_asset_in_terms_of_xrd = prices.get(&_key).unwrap();
-                let sd_price = parameters.deposit_balance / parameters.sd_balance;
-
-                let balance = value * sd_price;
+                let balance = calculate_deposit_value(
+                    value,
+                    parameters.deposit_balance,
+                    parameters.sd_balance,
+                );
                 deposit += balance * *cost_of_asset_in_terms_of_xrd;
             });
         (self.borrows.clone())
@@ -245,14 +840,29 @@ impl UserData {
             .for_each(|(_key, value)| {
                 let parameters = pool_parameters.get(&_key).unwrap().clone();
                 let cost_of_asset_in_terms_of_xrd = prices.get(&_key).unwrap();
-                let sb_price = parameters.borrow_balance / parameters.sb_balance;
-
-                let balance = value * sb_price;
+                let balance =
+                    calculate_debt_value(value, parameters.borrow_balance, parameters.sb_balance);
                 borrow += balance * *cost_of_asset_in_terms_of_xrd;
             });
+        (self.stable_borrows.clone())
+            .into_iter()
+            .for_each(|(_key, value)| {
+                let cost_of_asset_in_terms_of_xrd = prices.get(&_key).unwrap();
+                borrow += value.get_amount(get_current_time()) * *cost_of_asset_in_terms_of_xrd;
+            });
+        borrow += self.get_fixed_loans_in_xrd(prices);
         (deposit, borrow)
     }
 
+    fn get_fixed_loans_in_xrd(&self, prices: &HashMap<ResourceAddress, Decimal>) -> Decimal {
+        let mut loans = Decimal::ZERO;
+        for loan in self.fixed_loans.values() {
+            let cost_of_asset_in_terms_of_xrd = prices.get(&loan.resource_address).unwrap();
+            loans += loan.get_amount(get_current_time()) * *cost_of_asset_in_terms_of_xrd;
+        }
+        loans
+    }
+
     fn update_map(
         map: &mut IndexMap<ResourceAddress, Decimal>,
         key: ResourceAddress,
@@ -261,3 +871,228 @@ impl UserData {
         map.insert(key, value);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interest_rate_model::InterestRateModel;
+
+    fn collateral_address() -> ResourceAddress {
+        XRD
+    }
+
+    fn debt_address() -> ResourceAddress {
+        ResourceAddress::new_or_panic([EntityType::GlobalFungibleResourceManager as u8; 30])
+    }
+
+    fn pool(deposit_balance: Decimal, borrow_balance: Decimal) -> PoolParameters {
+        PoolParameters {
+            balances_updated_at: 0,
+            interest_rate_model: InterestRateModel::LinearKink {
+                base: dec!("0.02"),
+                base_multiplier: dec!("0.1"),
+                multiplier: dec!("1"),
+                kink: dec!("0.8"),
+            },
+            reserve_factor: dec!("0.1"),
+            ltv_ratio: dec!("0.8"),
+            min_collateral_ratio: Decimal::one(),
+            max_borrow_percent: dec!("0.1"),
+            max_liquidation_percent: dec!("0.5"),
+            liquidation_bonus: dec!("0.1"),
+            liquidation_reserve_factor: dec!("0.2"),
+            deposit_locked: false,
+            borrow_locked: false,
+            withdraw_locked: false,
+            repay_locked: false,
+            pool_reserve: dec!("0.2"),
+            deposit_limit: dec!("100000"),
+            borrow_cap: Decimal::ZERO,
+            deposit_balance,
+            sd_balance: deposit_balance,
+            borrow_balance,
+            sb_balance: borrow_balance,
+            reserve_balance: Decimal::ZERO,
+            vault_balance: deposit_balance - borrow_balance,
+            last_accrued_at: 0,
+            borrow_index: Decimal::ONE,
+            liquidity_index: Decimal::ONE,
+            stable_borrow_balance: Decimal::ZERO,
+            average_stable_rate: Decimal::ZERO,
+            stable_borrow_locked: true,
+            stable_rate_premium: dec!("0.02"),
+            stable_rebalance_utilisation: dec!("0.95"),
+            stable_rebalance_min_duration: 60 * 60,
+            stable_rebalance_since: 0,
+            fixed_borrow_balance: Decimal::ZERO,
+            fixed_borrow_locked: true,
+            fixed_loan_rate: dec!("0.08"),
+            fixed_loan_penalty_rate: dec!("0.1"),
+            fixed_loan_max_term: 105120,
+            flash_loan_fee: dec!("0.0009"),
+            flash_loan_balance: Decimal::ZERO,
+            liquidation_auction: false,
+            max_liquidation_bonus: dec!("0.1"),
+            liquidation_auction_duration: 12,
+            bad_debt: Decimal::ZERO,
+            bad_debt_covered: Decimal::ZERO,
+            safety_module_share: Decimal::ZERO,
+            isolated: false,
+            isolation_debt_ceiling: Decimal::ZERO,
+            isolation_debt: IndexMap::new(),
+            borrowable_in_isolation: false,
+        }
+    }
+
+    // Collateral and debt pools at a 1:1 sd/sb price, both assets priced 1 XRD
+    fn pools() -> IndexMap<ResourceAddress, PoolParameters> {
+        let mut pools = IndexMap::new();
+        pools.insert(collateral_address(), pool(dec!("10000"), Decimal::ZERO));
+        pools.insert(debt_address(), pool(dec!("10000"), dec!("5000")));
+        pools
+    }
+
+    fn prices() -> HashMap<ResourceAddress, Decimal> {
+        let mut prices = HashMap::new();
+        prices.insert(collateral_address(), Decimal::ONE);
+        prices.insert(debt_address(), Decimal::ONE);
+        prices
+    }
+
+    fn ltv_ratios() -> HashMap<ResourceAddress, Decimal> {
+        let mut ltv_ratios = HashMap::new();
+        ltv_ratios.insert(collateral_address(), dec!("0.8"));
+        ltv_ratios.insert(debt_address(), dec!("0.8"));
+        ltv_ratios
+    }
+
+    fn user(sd_balance: Decimal, sb_balance: Decimal) -> UserData {
+        let mut deposits = IndexMap::new();
+        deposits.insert(collateral_address(), sd_balance);
+        let mut borrows = IndexMap::new();
+        borrows.insert(debt_address(), sb_balance);
+        UserData {
+            key_image_url: String::new(),
+            name: String::new(),
+            minted_at: 0,
+            updated_at: 0,
+            deposits,
+            borrows,
+            stable_borrows: IndexMap::new(),
+            fixed_loans: IndexMap::new(),
+            liquidation_auction_started_at: 0,
+            e_mode_category: 0,
+            isolation_debt: IndexMap::new(),
+        }
+    }
+
+    fn is_user_liquidatable(user: &UserData) -> bool {
+        let loan_limit_used = user.get_loan_limit_used(&pools(), &ltv_ratios(), None, prices());
+        is_liquidatable(loan_limit_used, Decimal::ONE)
+    }
+
+    fn liquidate(
+        user: &mut UserData,
+        amount: Decimal,
+    ) -> Result<(Decimal, Decimal, Decimal), LiquidationError> {
+        user.try_on_liquidate(
+            amount,
+            debt_address(),
+            collateral_address(),
+            &pools(),
+            prices(),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_healthy_position_is_not_liquidatable() {
+        assert!(!is_user_liquidatable(&user(dec!("1000"), dec!("700"))));
+    }
+
+    #[test]
+    fn test_position_at_the_ltv_is_not_liquidatable() {
+        assert!(!is_user_liquidatable(&user(dec!("1000"), dec!("800"))));
+        assert!(is_user_liquidatable(&user(dec!("1000"), dec!("801"))));
+    }
+
+    #[test]
+    fn test_liquidation_seizes_collateral_with_the_bonus() {
+        let mut user = user(dec!("1000"), dec!("850"));
+        assert!(is_user_liquidatable(&user));
+
+        let (reward, platform_bonus, repaid) = liquidate(&mut user, dec!("425")).unwrap();
+
+        // The liquidator gets the repaid value plus the bonus net of the platform share
+        assert_eq!(reward, dec!("459"));
+        assert_eq!(platform_bonus, dec!("8.5"));
+        assert_eq!(repaid, dec!("425"));
+        assert_eq!(user.get_deposit(collateral_address()), dec!("532.5"));
+        assert_eq!(user.get_borrow(debt_address()), dec!("425"));
+    }
+
+    #[test]
+    fn test_liquidation_of_a_solvent_position_is_capped_by_the_max_liquidation_percent() {
+        let mut user = user(dec!("1000"), dec!("850"));
+
+        assert_eq!(
+            liquidate(&mut user, dec!("425.1")),
+            Err(LiquidationError::AmountAboveMaxRepayment)
+        );
+        // The user data is left unchanged
+        assert_eq!(user.get_deposit(collateral_address()), dec!("1000"));
+        assert_eq!(user.get_borrow(debt_address()), dec!("850"));
+    }
+
+    #[test]
+    fn test_liquidation_of_an_insolvent_position_takes_the_whole_collateral() {
+        // Debt plus bonus is above the collateral, the whole debt can be repaid
+        let mut user = user(dec!("1100"), dec!("1050"));
+
+        assert_eq!(
+            liquidate(&mut user.clone(), dec!("1000.1")),
+            Err(LiquidationError::AmountAboveMaxLiquidatingAmount)
+        );
+        let (reward, platform_bonus, repaid) = liquidate(&mut user, dec!("1000")).unwrap();
+
+        assert_eq!(reward, dec!("1080"));
+        assert_eq!(platform_bonus, dec!("20"));
+        assert_eq!(repaid, dec!("1000"));
+        assert_eq!(user.get_deposit(collateral_address()), Decimal::ZERO);
+        assert_eq!(user.get_borrow(debt_address()), dec!("50"));
+    }
+
+    #[test]
+    fn test_debt_left_without_collateral_is_bad_debt() {
+        let mut user = user(dec!("1100"), dec!("1050"));
+        liquidate(&mut user, dec!("1000")).unwrap();
+
+        let (borrows, stable_borrows, fixed_loans) = user.on_resolve_bad_debt();
+
+        assert_eq!(borrows.get(&debt_address()), Some(&dec!("50")));
+        assert!(stable_borrows.is_empty());
+        assert!(fixed_loans.is_empty());
+        assert_eq!(user.get_borrow(debt_address()), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_liquidation_is_limited_by_the_available_liquidity() {
+        let mut pools = pools();
+        // 20% of the collateral pool is kept as pool reserve, 900 can be paid out
+        pools.insert(collateral_address(), pool(dec!("10000"), dec!("7100")));
+        let mut user = user(dec!("1100"), dec!("1050"));
+
+        assert_eq!(
+            user.try_on_liquidate(
+                dec!("900"),
+                debt_address(),
+                collateral_address(),
+                &pools,
+                prices(),
+                None,
+            ),
+            Err(LiquidationError::AmountAboveAvailableLiquidity)
+        );
+        assert_eq!(user.get_deposit(collateral_address()), dec!("1100"));
+    }
+}
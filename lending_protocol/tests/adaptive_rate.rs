@@ -0,0 +1,111 @@
+use scrypto_test::prelude::*;
+
+use lending_protocol::calculations::*;
+
+const DAY: i64 = 24 * 60 * 60;
+const TARGET_UTILISATION: Decimal = dec!("0.8");
+const ADJUSTMENT_SPEED: Decimal = dec!("50");
+
+#[test]
+fn test_utilisation_error_is_normalised() {
+    assert_eq!(
+        calculate_utilisation_error(dec!("0.8"), TARGET_UTILISATION),
+        Decimal::ZERO
+    );
+    assert_eq!(
+        calculate_utilisation_error(dec!("0.9"), TARGET_UTILISATION),
+        dec!("0.5")
+    );
+    assert_eq!(
+        calculate_utilisation_error(Decimal::ONE, TARGET_UTILISATION),
+        Decimal::ONE
+    );
+    assert_eq!(
+        calculate_utilisation_error(dec!("0.4"), TARGET_UTILISATION),
+        dec!("-0.5")
+    );
+    assert_eq!(
+        calculate_utilisation_error(Decimal::ZERO, TARGET_UTILISATION),
+        dec!("-1")
+    );
+}
+
+#[test]
+fn test_rate_at_target_rises_above_target_utilisation() {
+    let rate_at_target = dec!("0.04");
+    let after_day = calculate_adapted_rate_at_target(
+        rate_at_target,
+        dec!("0.9"),
+        TARGET_UTILISATION,
+        ADJUSTMENT_SPEED,
+        DAY,
+    );
+    let after_week = calculate_adapted_rate_at_target(
+        rate_at_target,
+        dec!("0.9"),
+        TARGET_UTILISATION,
+        ADJUSTMENT_SPEED,
+        7 * DAY,
+    );
+
+    assert!(after_day > rate_at_target);
+    assert!(after_week > after_day);
+}
+
+#[test]
+fn test_rate_at_target_decays_below_target_utilisation() {
+    let rate_at_target = dec!("0.04");
+    let after_day = calculate_adapted_rate_at_target(
+        rate_at_target,
+        dec!("0.4"),
+        TARGET_UTILISATION,
+        ADJUSTMENT_SPEED,
+        DAY,
+    );
+    let after_week = calculate_adapted_rate_at_target(
+        rate_at_target,
+        dec!("0.4"),
+        TARGET_UTILISATION,
+        ADJUSTMENT_SPEED,
+        7 * DAY,
+    );
+
+    assert!(after_day < rate_at_target);
+    assert!(after_week < after_day);
+    assert!(after_week > Decimal::ZERO);
+}
+
+#[test]
+fn test_rate_at_target_holds_at_target_utilisation() {
+    assert_eq!(
+        calculate_adapted_rate_at_target(
+            dec!("0.04"),
+            TARGET_UTILISATION,
+            TARGET_UTILISATION,
+            ADJUSTMENT_SPEED,
+            7 * DAY,
+        ),
+        dec!("0.04")
+    );
+}
+
+#[test]
+fn test_rate_at_target_moves_faster_further_from_target() {
+    let rate_at_target = dec!("0.04");
+    let slightly_above = calculate_adapted_rate_at_target(
+        rate_at_target,
+        dec!("0.85"),
+        TARGET_UTILISATION,
+        ADJUSTMENT_SPEED,
+        DAY,
+    );
+    let fully_utilised = calculate_adapted_rate_at_target(
+        rate_at_target,
+        Decimal::ONE,
+        TARGET_UTILISATION,
+        ADJUSTMENT_SPEED,
+        DAY,
+    );
+
+    assert!(fully_utilised > slightly_above);
+}
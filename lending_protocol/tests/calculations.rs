@@ -0,0 +1,94 @@
+use scrypto_test::prelude::*;
+
+use lending_protocol::calculations::*;
+
+const HOUR: i64 = 60 * 60;
+const WEEK: i64 = 7 * 24 * HOUR;
+const BLOCK: i64 = 5;
+
+fn assert_close(left: Decimal, right: Decimal) {
+    let difference = if left > right {
+        left - right
+    } else {
+        right - left
+    };
+    assert!(
+        difference <= right * dec!("0.000000001"),
+        "{} and {} differ by {}",
+        left,
+        right,
+        difference
+    );
+}
+
+#[test]
+fn test_compounded_rate_is_zero_without_elapsed_time() {
+    assert_eq!(calculate_compounded_rate(dec!("0.1"), 0), Decimal::ZERO);
+    assert_eq!(calculate_compounded_rate(Decimal::ZERO, WEEK), Decimal::ZERO);
+}
+
+#[test]
+fn test_compounded_rate_exceeds_linear_rate() {
+    let compounded = calculate_compounded_rate(dec!("0.1"), SECONDS_PER_YEAR);
+
+    // Continuous compounding of 10% for a year gives e^0.1 - 1
+    assert!(compounded > dec!("0.1"));
+    assert!(compounded > dec!("0.105170") && compounded < dec!("0.105171"));
+}
+
+#[test]
+fn test_weekly_and_per_block_accrual_match() {
+    let borrow_rate = dec!("0.25");
+    let mut borrow_balance = dec!("1000000");
+
+    let weekly_growth = calculate_compounded_rate(borrow_rate, WEEK);
+    let weekly_balance =
+        borrow_balance + calculate_interests(borrow_balance, weekly_growth, dec!("0.2")).0;
+
+    let block_growth = calculate_compounded_rate(borrow_rate, BLOCK);
+    for _ in 0..WEEK / BLOCK {
+        borrow_balance += calculate_interests(borrow_balance, block_growth, dec!("0.2")).0;
+    }
+
+    assert_close(borrow_balance, weekly_balance);
+}
+
+#[test]
+fn test_weekly_and_hourly_accrual_split_interest_the_same() {
+    let borrow_rate = dec!("0.08");
+    let reserve_factor = dec!("0.2");
+    let borrow_balance = dec!("500000");
+
+    let weekly_growth = calculate_compounded_rate(borrow_rate, WEEK);
+    let (_, weekly_reserve, weekly_deposit) =
+        calculate_interests(borrow_balance, weekly_growth, reserve_factor);
+
+    let hourly_growth = calculate_compounded_rate(borrow_rate, HOUR);
+    let mut balance = borrow_balance;
+    let mut reserve_interest = Decimal::ZERO;
+    let mut deposit_interest = Decimal::ZERO;
+    for _ in 0..WEEK / HOUR {
+        let interests = calculate_interests(balance, hourly_growth, reserve_factor);
+        balance += interests.0;
+        reserve_interest += interests.1;
+        deposit_interest += interests.2;
+    }
+
+    assert_close(reserve_interest, weekly_reserve);
+    assert_close(deposit_interest, weekly_deposit);
+}
+
+#[test]
+fn test_index_follows_compounded_growth() {
+    let borrow_rate = dec!("0.12");
+    let growth = calculate_compounded_rate(borrow_rate, WEEK);
+    let weekly_index = calculate_index(Decimal::ONE, growth);
+
+    let daily_growth = calculate_compounded_rate(borrow_rate, WEEK / 7);
+    let mut index = Decimal::ONE;
+    for _ in 0..7 {
+        index = calculate_index(index, daily_growth);
+    }
+
+    assert_close(index, weekly_index);
+}
@@ -0,0 +1,92 @@
+use scrypto_test::prelude::*;
+
+use lending_protocol::calculations::*;
+
+const ISOLATED_ASSET: ResourceAddress = ACCOUNT_OWNER_BADGE;
+const BORROWED_ASSET: ResourceAddress = XRD;
+const OTHER_BORROWED_ASSET: ResourceAddress = PACKAGE_OWNER_BADGE;
+
+fn isolation_debt(
+    loans: &[(ResourceAddress, Decimal)],
+) -> IndexMap<ResourceAddress, IndexMap<ResourceAddress, Decimal>> {
+    let mut isolation_debt = IndexMap::new();
+    if !loans.is_empty() {
+        isolation_debt.insert(ISOLATED_ASSET, loans.iter().copied().collect());
+    }
+    isolation_debt
+}
+
+fn get_change(
+    changes: &IndexMap<ResourceAddress, IndexMap<ResourceAddress, Decimal>>,
+    resource_address: ResourceAddress,
+) -> Decimal {
+    *changes
+        .get(&ISOLATED_ASSET)
+        .unwrap()
+        .get(&resource_address)
+        .unwrap()
+}
+
+#[test]
+fn test_new_loan_is_counted_in_full() {
+    let changes = calculate_isolation_debt_changes(
+        &isolation_debt(&[]),
+        &isolation_debt(&[(BORROWED_ASSET, dec!("100"))]),
+    );
+
+    assert_eq!(get_change(&changes, BORROWED_ASSET), dec!("100"));
+}
+
+#[test]
+fn test_interest_is_counted_in_units_of_the_borrowed_asset() {
+    let changes = calculate_isolation_debt_changes(
+        &isolation_debt(&[(BORROWED_ASSET, dec!("100"))]),
+        &isolation_debt(&[(BORROWED_ASSET, dec!("105"))]),
+    );
+
+    assert_eq!(get_change(&changes, BORROWED_ASSET), dec!("5"));
+}
+
+#[test]
+fn test_partial_repay_releases_the_repaid_amount() {
+    let changes = calculate_isolation_debt_changes(
+        &isolation_debt(&[
+            (BORROWED_ASSET, dec!("105")),
+            (OTHER_BORROWED_ASSET, dec!("20")),
+        ]),
+        &isolation_debt(&[
+            (BORROWED_ASSET, dec!("45")),
+            (OTHER_BORROWED_ASSET, dec!("20")),
+        ]),
+    );
+
+    assert_eq!(get_change(&changes, BORROWED_ASSET), dec!("-60"));
+    assert_eq!(get_change(&changes, OTHER_BORROWED_ASSET), Decimal::ZERO);
+}
+
+#[test]
+fn test_full_repay_releases_everything_counted() {
+    let changes = calculate_isolation_debt_changes(
+        &isolation_debt(&[
+            (BORROWED_ASSET, dec!("105")),
+            (OTHER_BORROWED_ASSET, dec!("20")),
+        ]),
+        &isolation_debt(&[(OTHER_BORROWED_ASSET, dec!("20"))]),
+    );
+
+    assert_eq!(get_change(&changes, BORROWED_ASSET), dec!("-105"));
+}
+
+#[test]
+fn test_withdrawn_collateral_releases_its_loans() {
+    let changes = calculate_isolation_debt_changes(
+        &isolation_debt(&[
+            (BORROWED_ASSET, dec!("105")),
+            (OTHER_BORROWED_ASSET, dec!("20")),
+        ]),
+        &isolation_debt(&[]),
+    );
+
+    assert_eq!(get_change(&changes, BORROWED_ASSET), dec!("-105"));
+    assert_eq!(get_change(&changes, OTHER_BORROWED_ASSET), dec!("-20"));
+}
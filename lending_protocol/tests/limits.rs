@@ -0,0 +1,96 @@
+use scrypto_test::prelude::*;
+
+use lending_protocol::calculations::*;
+
+#[test]
+fn test_max_amount_is_the_tightest_limit() {
+    let limits = [
+        ("AvailableLiquidity", dec!("500")),
+        ("CollateralHeadroom", dec!("120")),
+        ("MaxBorrowPercent", dec!("300")),
+    ];
+
+    assert_eq!(
+        select_max_amount(&limits),
+        ("CollateralHeadroom", dec!("120"))
+    );
+}
+
+#[test]
+fn test_max_amount_keeps_the_first_of_equal_limits() {
+    let limits = [
+        ("AvailableLiquidity", dec!("100")),
+        ("BorrowCap", dec!("100")),
+    ];
+
+    assert_eq!(
+        select_max_amount(&limits),
+        ("AvailableLiquidity", dec!("100"))
+    );
+}
+
+#[test]
+fn test_max_amount_is_not_negative() {
+    let limits = [
+        ("AvailableLiquidity", dec!("500")),
+        ("CollateralHeadroom", dec!("-40")),
+    ];
+
+    assert_eq!(
+        select_max_amount(&limits),
+        ("CollateralHeadroom", Decimal::ZERO)
+    );
+}
+
+#[test]
+fn test_borrow_headroom_of_the_unused_collateral() {
+    // 1000 XRD of LTV-weighted collateral, 600 XRD of loans, asset worth 2 XRD
+    assert_eq!(
+        calculate_borrow_headroom(dec!("1000"), dec!("600"), dec!("2")),
+        dec!("200")
+    );
+    assert!(calculate_borrow_headroom(dec!("600"), dec!("1000"), dec!("2")) < Decimal::ZERO);
+}
+
+#[test]
+fn test_withdraw_headroom_removes_the_ltv_weighting() {
+    // 400 XRD of unused weighted collateral at an LTV of 0.8 is 500 XRD of deposits
+    assert_eq!(
+        calculate_withdraw_headroom(dec!("1000"), dec!("600"), dec!("0.8"), dec!("2")),
+        dec!("250")
+    );
+}
+
+#[test]
+fn test_max_borrow_percent_headroom() {
+    assert_eq!(
+        calculate_max_borrow_percent_headroom(dec!("1000"), dec!("0.1"), dec!("30")),
+        dec!("70")
+    );
+    assert_eq!(
+        calculate_max_borrow_percent_headroom(dec!("1000"), dec!("0.1"), dec!("100")),
+        Decimal::ZERO
+    );
+}
+
+#[test]
+fn test_max_borrow_percent_withdraw() {
+    // 30 borrowed at a max of 10% needs 300 of deposits to stay in the pool
+    assert_eq!(
+        calculate_max_borrow_percent_withdraw(dec!("1000"), dec!("0.1"), dec!("30")),
+        dec!("700")
+    );
+    assert_eq!(
+        calculate_max_borrow_percent_withdraw(dec!("1000"), Decimal::ZERO, Decimal::ZERO),
+        dec!("1000")
+    );
+}
+
+#[test]
+fn test_available_liquidity_keeps_the_pool_reserve() {
+    // 1000 deposited, 100 of reserve, 500 lent out and 20% kept in the pool
+    assert_eq!(
+        calculate_available_liquidity(dec!("1000"), dec!("500"), dec!("100"), dec!("0.2")),
+        dec!("200")
+    );
+}
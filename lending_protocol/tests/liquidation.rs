@@ -0,0 +1,172 @@
+use scrypto_test::prelude::*;
+
+use lending_protocol::calculations::*;
+
+const MIN_COLLATERAL_RATIO: Decimal = Decimal::ONE;
+
+fn liquidatable(collateral: Decimal, loan: Decimal) -> bool {
+    is_liquidatable(
+        calculate_loan_limit_used(collateral, loan),
+        MIN_COLLATERAL_RATIO,
+    )
+}
+
+#[test]
+fn test_position_without_loans_uses_no_limit() {
+    assert_eq!(
+        calculate_loan_limit_used(dec!("1000"), Decimal::ZERO),
+        Decimal::ZERO
+    );
+    assert_eq!(
+        calculate_loan_limit_used(Decimal::ZERO, Decimal::ZERO),
+        Decimal::ZERO
+    );
+    assert!(!liquidatable(dec!("1000"), Decimal::ZERO));
+}
+
+#[test]
+fn test_healthy_position_can_not_be_liquidated() {
+    let loan_limit_used = calculate_loan_limit_used(dec!("1000"), dec!("600"));
+
+    assert_eq!(loan_limit_used, dec!("0.6"));
+    assert!(!is_liquidatable(loan_limit_used, MIN_COLLATERAL_RATIO));
+}
+
+#[test]
+fn test_borderline_position_can_not_be_liquidated() {
+    // Loans equal to the collateral are still allowed
+    let loan_limit_used = calculate_loan_limit_used(dec!("1000"), dec!("1000"));
+
+    assert_eq!(loan_limit_used, MIN_COLLATERAL_RATIO);
+    assert!(!is_liquidatable(loan_limit_used, MIN_COLLATERAL_RATIO));
+}
+
+#[test]
+fn test_position_just_above_the_ratio_can_be_liquidated() {
+    assert!(liquidatable(dec!("1000"), dec!("1000.000000000000000001")));
+}
+
+#[test]
+fn test_position_without_collateral_can_be_liquidated() {
+    assert_eq!(
+        calculate_loan_limit_used(Decimal::ZERO, dec!("1")),
+        Decimal::MAX
+    );
+    assert!(liquidatable(Decimal::ZERO, dec!("1")));
+}
+
+#[test]
+fn test_solvent_position_is_liquidated_up_to_the_max_percent() {
+    // 900 of debt plus a 10% bonus is still covered by 1000 of deposits
+    let percent =
+        calculate_max_liquidation_percent(dec!("900"), dec!("1000"), dec!("0.1"), dec!("0.5"));
+
+    assert_eq!(percent, dec!("0.5"));
+}
+
+#[test]
+fn test_insolvent_position_is_liquidated_in_full() {
+    let percent =
+        calculate_max_liquidation_percent(dec!("950"), dec!("1000"), dec!("0.1"), dec!("0.5"));
+
+    assert_eq!(percent, Decimal::ONE);
+    assert!(liquidatable(dec!("800"), dec!("950")));
+}
+
+#[test]
+fn test_auction_bonus_starts_at_zero() {
+    assert_eq!(
+        calculate_auction_bonus(dec!("0.1"), 100, 100, 10),
+        Decimal::ZERO
+    );
+}
+
+#[test]
+fn test_auction_bonus_ramps_up_linearly() {
+    assert_eq!(
+        calculate_auction_bonus(dec!("0.1"), 100, 103, 10),
+        dec!("0.03")
+    );
+    assert_eq!(
+        calculate_auction_bonus(dec!("0.1"), 100, 105, 10),
+        dec!("0.05")
+    );
+}
+
+#[test]
+fn test_auction_bonus_is_capped_at_the_max() {
+    assert_eq!(
+        calculate_auction_bonus(dec!("0.1"), 100, 110, 10),
+        dec!("0.1")
+    );
+    assert_eq!(
+        calculate_auction_bonus(dec!("0.1"), 100, 500, 10),
+        dec!("0.1")
+    );
+}
+
+#[test]
+fn test_bad_debt_is_covered_by_the_reserve_first() {
+    assert_eq!(
+        calculate_bad_debt_split(dec!("40"), dec!("100")),
+        (dec!("40"), Decimal::ZERO)
+    );
+}
+
+#[test]
+fn test_bad_debt_above_the_reserve_is_socialised() {
+    assert_eq!(
+        calculate_bad_debt_split(dec!("140"), dec!("100")),
+        (dec!("100"), dec!("40"))
+    );
+    assert_eq!(
+        calculate_bad_debt_split(dec!("140"), Decimal::ZERO),
+        (Decimal::ZERO, dec!("140"))
+    );
+}
+
+#[test]
+fn test_health_factor_of_healthy_and_liquidatable_positions() {
+    assert_eq!(
+        calculate_health_factor(dec!("0.5"), MIN_COLLATERAL_RATIO),
+        dec!("2")
+    );
+    assert_eq!(
+        calculate_health_factor(MIN_COLLATERAL_RATIO, MIN_COLLATERAL_RATIO),
+        Decimal::ONE
+    );
+    assert!(calculate_health_factor(dec!("1.25"), MIN_COLLATERAL_RATIO) < Decimal::ONE);
+    assert_eq!(
+        calculate_health_factor(Decimal::ZERO, MIN_COLLATERAL_RATIO),
+        Decimal::MAX
+    );
+}
+
+#[test]
+fn test_health_factor_below_one_matches_liquidatable() {
+    let liquidation_threshold = dec!("0.8");
+
+    assert_eq!(
+        calculate_health_factor(dec!("0.4"), liquidation_threshold),
+        dec!("2")
+    );
+    for loan_limit_used in [dec!("0.4"), dec!("0.8"), dec!("0.8000001"), dec!("1.2")] {
+        assert_eq!(
+            calculate_health_factor(loan_limit_used, liquidation_threshold) < Decimal::ONE,
+            is_liquidatable(loan_limit_used, liquidation_threshold)
+        );
+    }
+}
+
+#[test]
+fn test_liquidation_price_drop() {
+    // Collateral worth twice the threshold survives a 50% price drop
+    assert_eq!(calculate_liquidation_price_drop(dec!("2")), dec!("0.5"));
+    assert_eq!(calculate_liquidation_price_drop(dec!("1.25")), dec!("0.2"));
+    assert_eq!(
+        calculate_liquidation_price_drop(Decimal::ONE),
+        Decimal::ZERO
+    );
+    assert_eq!(calculate_liquidation_price_drop(dec!("0.8")), Decimal::ZERO);
+    assert_eq!(calculate_liquidation_price_drop(Decimal::MAX), Decimal::ONE);
+}
@@ -0,0 +1,123 @@
+use scrypto_test::prelude::*;
+
+use lending_protocol::calculations::*;
+
+// 1001 tokens backed by 333 sd/sb tokens, so conversions never divide evenly
+const TOTAL: Decimal = dec!("1001");
+const TOTAL_S: Decimal = dec!("333");
+
+#[test]
+fn test_conversions_round_in_favour_of_the_protocol() {
+    let amount = dec!("1");
+
+    assert!(
+        calculate_sd_to_mint(amount, TOTAL, TOTAL_S) < calculate_sd_to_burn(amount, TOTAL, TOTAL_S)
+    );
+    assert!(
+        calculate_sb_to_burn(amount, TOTAL, TOTAL_S) < calculate_sb_to_mint(amount, TOTAL, TOTAL_S)
+    );
+    assert!(
+        calculate_deposit_value(amount, TOTAL, TOTAL_S)
+            < calculate_debt_value(amount, TOTAL, TOTAL_S)
+    );
+    assert_eq!(
+        calculate_sd_to_burn(amount, TOTAL, TOTAL_S) - calculate_sd_to_mint(amount, TOTAL, TOTAL_S),
+        Decimal::from_attos(I192::from(1))
+    );
+}
+
+#[test]
+fn test_conversions_are_one_to_one_for_empty_pools() {
+    let amount = dec!("12.5");
+
+    assert_eq!(
+        calculate_sd_to_mint(amount, Decimal::ZERO, Decimal::ZERO),
+        amount
+    );
+    assert_eq!(
+        calculate_sb_to_mint(amount, Decimal::ZERO, Decimal::ZERO),
+        amount
+    );
+    assert_eq!(
+        calculate_deposit_value(amount, Decimal::ZERO, Decimal::ZERO),
+        amount
+    );
+    assert_eq!(
+        calculate_debt_value(amount, Decimal::ZERO, Decimal::ZERO),
+        amount
+    );
+}
+
+#[test]
+fn test_deposit_and_withdraw_loop_does_not_extract_value() {
+    let mut deposit_balance = TOTAL;
+    let mut sd_balance = TOTAL_S;
+    let mut user_sd = Decimal::ZERO;
+    let mut wallet = dec!("10");
+    let initial_wallet = wallet;
+
+    for i in 1..1000 {
+        // Tiny and odd amounts hit the rounding on every step
+        let amount = Decimal::from_attos(I192::from(i * 7));
+        let minted = calculate_sd_to_mint(amount, deposit_balance, sd_balance);
+        wallet -= amount;
+        deposit_balance += amount;
+        sd_balance += minted;
+        user_sd += minted;
+
+        let withdrawn = calculate_deposit_value(user_sd, deposit_balance, sd_balance);
+        let burned = calculate_sd_to_burn(withdrawn, deposit_balance, sd_balance);
+        assert!(burned <= user_sd);
+        wallet += withdrawn;
+        deposit_balance -= withdrawn;
+        sd_balance -= burned;
+        user_sd -= burned;
+    }
+
+    assert!(wallet <= initial_wallet);
+    // Value of the remaining sd tokens never drops below the initial exchange rate
+    assert!(
+        PreciseDecimal::from(deposit_balance) * PreciseDecimal::from(TOTAL_S)
+            >= PreciseDecimal::from(TOTAL) * PreciseDecimal::from(sd_balance)
+    );
+}
+
+#[test]
+fn test_borrow_and_repay_loop_does_not_extract_value() {
+    let mut borrow_balance = TOTAL;
+    let mut sb_balance = TOTAL_S;
+    let mut wallet = dec!("10");
+    let initial_wallet = wallet;
+
+    for i in 1..1000 {
+        let amount = Decimal::from_attos(I192::from(i * 7));
+        let minted = calculate_sb_to_mint(amount, borrow_balance, sb_balance);
+        wallet += amount;
+        borrow_balance += amount;
+        sb_balance += minted;
+
+        // Repay the whole debt
+        let debt = calculate_debt_value(minted, borrow_balance, sb_balance);
+        let burned = calculate_sb_to_burn(debt, borrow_balance, sb_balance).min(minted);
+        assert_eq!(burned, minted);
+        wallet -= debt;
+        borrow_balance -= debt;
+        sb_balance -= burned;
+    }
+
+    assert!(wallet <= initial_wallet);
+    assert_eq!(sb_balance, TOTAL_S);
+    // Debt repaid in excess of the borrowed amount is held by the pool
+    assert_eq!(initial_wallet - wallet + borrow_balance, TOTAL);
+}
+
+#[test]
+fn test_debt_interest_rounds_up() {
+    let debt = Decimal::from_attos(I192::from(3));
+    let rate = dec!("0.5");
+
+    assert_eq!(
+        calculate_debt_interest(debt, rate),
+        Decimal::from_attos(I192::from(2))
+    );
+}
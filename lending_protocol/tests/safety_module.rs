@@ -0,0 +1,122 @@
+use scrypto_test::prelude::*;
+
+use lending_protocol::calculations::*;
+
+const MAX_SLASH_PERCENT: Decimal = dec!("0.3");
+
+#[test]
+fn test_first_stake_is_minted_one_to_one() {
+    assert_eq!(
+        calculate_sd_to_mint(dec!("1000"), Decimal::ZERO, Decimal::ZERO),
+        dec!("1000")
+    );
+    assert_eq!(
+        calculate_token_price(Decimal::ZERO, Decimal::ZERO),
+        Decimal::ONE
+    );
+}
+
+#[test]
+fn test_rewards_increase_the_stake_price() {
+    // 1000 staked for 1000 stake tokens, then 100 of rewards added
+    let staked = dec!("1000") + dec!("100");
+    let stake_supply = dec!("1000");
+
+    assert_eq!(calculate_token_price(staked, stake_supply), dec!("1.1"));
+    // Later stakers do not share the rewards added before them
+    assert_eq!(
+        calculate_sd_to_mint(dec!("110"), staked, stake_supply),
+        dec!("100")
+    );
+    assert_eq!(
+        calculate_deposit_value(dec!("1000"), staked, stake_supply),
+        dec!("1100")
+    );
+}
+
+#[test]
+fn test_slash_decreases_the_stake_price() {
+    let slashed = calculate_max_slash_amount(dec!("1000"), MAX_SLASH_PERCENT);
+    let staked = dec!("1000") - slashed;
+    let stake_supply = dec!("1000");
+
+    assert_eq!(calculate_token_price(staked, stake_supply), dec!("0.7"));
+    // Stake tokens in cooldown are slashed the same way
+    assert_eq!(
+        calculate_deposit_value(dec!("500"), staked, stake_supply),
+        dec!("350")
+    );
+}
+
+#[test]
+fn test_slash_is_capped_by_the_max_slash_percent() {
+    assert_eq!(
+        calculate_max_slash_amount(dec!("1000"), MAX_SLASH_PERCENT),
+        dec!("300")
+    );
+    assert_eq!(
+        calculate_max_slash_amount(dec!("1000"), Decimal::ZERO),
+        Decimal::ZERO
+    );
+    assert_eq!(
+        calculate_max_slash_amount(Decimal::ZERO, MAX_SLASH_PERCENT),
+        Decimal::ZERO
+    );
+}
+
+#[test]
+fn test_stake_is_rejected_when_stake_tokens_have_no_staked_asset() {
+    assert!(!is_safety_module_drained(Decimal::ZERO, Decimal::ZERO));
+    assert!(!is_safety_module_drained(dec!("700"), dec!("1000")));
+    // A new stake would be minted 1:1 and share the staked amount with the old tokens
+    assert!(is_safety_module_drained(Decimal::ZERO, dec!("1000")));
+}
+
+#[test]
+fn test_unstake_claim_waits_for_the_cooldown() {
+    let cooldown_epochs = 5;
+    let claimable_at = 10 + cooldown_epochs;
+
+    assert!(!is_unstake_claimable(10, claimable_at));
+    assert!(!is_unstake_claimable(14, claimable_at));
+    assert!(is_unstake_claimable(15, claimable_at));
+    assert!(is_unstake_claimable(100, claimable_at));
+}
+
+#[test]
+fn test_bad_debt_cover_is_limited_to_the_uncovered_part() {
+    assert_eq!(
+        calculate_uncovered_bad_debt(dec!("500"), dec!("200")),
+        dec!("300")
+    );
+    assert_eq!(
+        calculate_uncovered_bad_debt(dec!("500"), dec!("500")),
+        Decimal::ZERO
+    );
+    assert_eq!(
+        calculate_uncovered_bad_debt(Decimal::ZERO, Decimal::ZERO),
+        Decimal::ZERO
+    );
+}
+
+#[test]
+fn test_bad_debt_cover_returns_the_slash_to_depositors() {
+    // 1000 deposited for 1000 sd tokens, 400 written off as bad debt
+    let deposit_balance = dec!("600");
+    let sd_balance = dec!("1000");
+    let uncovered_bad_debt = calculate_uncovered_bad_debt(dec!("400"), Decimal::ZERO);
+    let covered = uncovered_bad_debt.min(calculate_max_slash_amount(
+        dec!("1000"),
+        MAX_SLASH_PERCENT,
+    ));
+
+    assert_eq!(covered, dec!("300"));
+    assert_eq!(
+        calculate_token_price(deposit_balance + covered, sd_balance),
+        dec!("0.9")
+    );
+    assert_eq!(
+        calculate_uncovered_bad_debt(dec!("400"), covered),
+        dec!("100")
+    );
+}
@@ -0,0 +1,51 @@
+use scrypto_test::prelude::*;
+
+use lending_protocol::calculations::*;
+
+const THRESHOLD: Decimal = dec!("0.95");
+const HOUR: i64 = 60 * 60;
+const START: i64 = 1_700_000_000;
+
+#[test]
+fn test_time_above_threshold_starts_at_the_previous_accrual() {
+    // Utilisation was above the threshold for the whole period since the last accrual
+    assert_eq!(
+        calculate_stable_rebalance_since(0, START, dec!("0.97"), THRESHOLD),
+        START
+    );
+    assert_eq!(
+        calculate_stable_rebalance_since(START, START + HOUR, dec!("0.99"), THRESHOLD),
+        START
+    );
+}
+
+#[test]
+fn test_time_above_threshold_resets_below_it() {
+    assert_eq!(
+        calculate_stable_rebalance_since(START, START + HOUR, dec!("0.9"), THRESHOLD),
+        0
+    );
+    assert_eq!(
+        calculate_stable_rebalance_since(0, START, dec!("0.9"), THRESHOLD),
+        0
+    );
+}
+
+#[test]
+fn test_utilisation_pushed_up_in_the_same_transaction_does_not_allow_a_rebalance() {
+    // A borrow accrues at the low utilisation, the rebalance right after it accrues
+    // with no time elapsed at the high one
+    let since = calculate_stable_rebalance_since(0, START, dec!("0.5"), THRESHOLD);
+    let since = calculate_stable_rebalance_since(since, START, dec!("0.99"), THRESHOLD);
+
+    assert_eq!(since, START);
+    assert!(!is_stable_rebalance_allowed(since, START, HOUR));
+}
+
+#[test]
+fn test_rebalance_is_allowed_after_the_min_duration() {
+    assert!(!is_stable_rebalance_allowed(0, START, HOUR));
+    assert!(!is_stable_rebalance_allowed(START, START + HOUR - 1, HOUR));
+    assert!(is_stable_rebalance_allowed(START, START + HOUR, HOUR));
+    assert!(is_stable_rebalance_allowed(START, START, 0));
+}
@@ -1,6 +1,28 @@
 use scrypto::prelude::*;
 
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct PoolTakeEvent {
+    pub amount: Decimal,
+    pub vault_amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct PoolPutEvent {
+    pub amount: Decimal,
+    pub vault_amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct PoolBalancesUpdateEvent {
+    pub deposit: Decimal,
+    pub sd_balance: Decimal,
+    pub borrow: Decimal,
+    pub sb_balance: Decimal,
+    pub reserve: Decimal,
+}
+
 #[blueprint]
+#[events(PoolTakeEvent, PoolPutEvent, PoolBalancesUpdateEvent)]
 mod pool {
     enable_method_auth! {
         roles {
@@ -60,14 +82,15 @@ mod pool {
             sb_balance: Decimal,
             reserve: Decimal,
         ) -> Bucket {
-            self.borrow = borrow;
-            self.deposit = deposit;
-            self.sd_balance = sd_balance;
-            self.sb_balance = sb_balance;
-            self.reserve = reserve;
-            self.updated_at = Runtime::current_epoch().number();
-            self.liquidity_pool
-                .take_advanced(amount, WithdrawStrategy::Rounded(RoundingMode::ToZero))
+            self.set_balances(deposit, sd_balance, borrow, sb_balance, reserve);
+            let bucket = self
+                .liquidity_pool
+                .take_advanced(amount, WithdrawStrategy::Rounded(RoundingMode::ToZero));
+            Runtime::emit_event(PoolTakeEvent {
+                amount: bucket.amount(),
+                vault_amount: self.liquidity_pool.amount(),
+            });
+            bucket
         }
 
         pub fn put(
@@ -79,13 +102,45 @@ mod pool {
             sb_balance: Decimal,
             reserve: Decimal,
         ) {
+            self.set_balances(deposit, sd_balance, borrow, sb_balance, reserve);
+            let amount = bucket.amount();
+            self.liquidity_pool.put(bucket);
+            Runtime::emit_event(PoolPutEvent {
+                amount,
+                vault_amount: self.liquidity_pool.amount(),
+            });
+        }
+
+        // Balances are owned by the lending protocol, the pool only keeps their latest copy
+        fn set_balances(
+            &mut self,
+            deposit: Decimal,
+            sd_balance: Decimal,
+            borrow: Decimal,
+            sb_balance: Decimal,
+            reserve: Decimal,
+        ) {
+            assert!(
+                deposit >= Decimal::ZERO
+                    && sd_balance >= Decimal::ZERO
+                    && borrow >= Decimal::ZERO
+                    && sb_balance >= Decimal::ZERO
+                    && reserve >= Decimal::ZERO,
+                "Pool balances must not be negative."
+            );
             self.borrow = borrow;
             self.deposit = deposit;
             self.sd_balance = sd_balance;
             self.sb_balance = sb_balance;
             self.reserve = reserve;
             self.updated_at = Runtime::current_epoch().number();
-            self.liquidity_pool.put(bucket)
+            Runtime::emit_event(PoolBalancesUpdateEvent {
+                deposit,
+                sd_balance,
+                borrow,
+                sb_balance,
+                reserve,
+            });
         }
 
         pub fn get_pool_balances(&mut self) -> (Decimal, Decimal, Decimal, Decimal, Decimal) {